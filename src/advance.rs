@@ -1,25 +1,137 @@
 use std::{
-    fs,
-    path::Path,
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
-use std::{
-    path::{ PathBuf},
-    time::{SystemTime, UNIX_EPOCH, Duration},
-};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 
 use colored::*;
+use fs2::FileExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{
+    builds::{list_builds, prune, verify_build, write_manifest},
+    config::Config,
+    git::{authenticated_git, run_git},
+    outln,
+    pipeline::matching_paths,
+    project::LaravelProject,
+    state::StateManager,
+    toolchain::Toolchain,
+};
+
+/// Failed builds of this run per build directory, with the project that ran
+/// them, so other projects don't build the same hash again.
+static FAILED_BUILDS: Lazy<Mutex<HashMap<PathBuf, (PathBuf, String)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Where a project's JS assets are built from and cached
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BuildMode {
+    /// Built from the shared parent repo and cached in its `.builds`, for forks of the same app
+    #[default]
+    Shared,
+    /// Built from the project's own repo and cached in its `.builds`
+    Project,
+    /// Built from the project's own repo and cached in the given directory
+    Dir(String),
+}
+
+impl std::fmt::Display for BuildMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BuildMode::Shared => write!(f, "shared"),
+            BuildMode::Project => write!(f, "project"),
+            BuildMode::Dir(dir) => write!(f, "dir {}", dir),
+        }
+    }
+}
+
+/// The repo a project's builds are made from and the directory they are cached in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildSite {
+    /// Worktrees of this repo are checked out to build
+    pub repo: PathBuf,
+    /// Holds the `<key>` builds plus `.staging`, `.worktrees`, `.locks` and `.pnpm-store`
+    pub builds_dir: PathBuf,
+}
+
+impl BuildSite {
+    /// The shared parent repo and its `.builds`
+    pub fn shared(parent: &Path) -> Self {
+        BuildSite {
+            repo: parent.to_path_buf(),
+            builds_dir: parent.join(".builds"),
+        }
+    }
+
+    /// Where `project` builds according to its `build_mode`
+    pub fn for_project(project: &Path, parent: &Path, config: &Config) -> Self {
+        let mode = config
+            .projects
+            .get(&project_name(project))
+            .map(|p| p.build_mode.clone())
+            .unwrap_or_default();
+
+        match mode {
+            BuildMode::Shared => BuildSite::shared(parent),
+            BuildMode::Project => BuildSite {
+                repo: project.to_path_buf(),
+                builds_dir: project.join(".builds"),
+            },
+            BuildMode::Dir(dir) => BuildSite {
+                repo: project.to_path_buf(),
+                builds_dir: PathBuf::from(dir),
+            },
+        }
+    }
+}
 
-use crate::{git::get_git_credentials, project::LaravelProject};
+fn project_name(project: &Path) -> String {
+    project.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+/// Written into a build once it finished, builds without it are incomplete
+pub const BUILD_COMPLETE: &str = ".sahakari-complete";
+
+/// Whether `build_dir` holds a finished build
+pub fn is_complete_build(build_dir: &Path) -> bool {
+    build_dir.join(BUILD_COMPLETE).is_file()
+}
+
+/// Take the lock `<builds_dir>/.locks/<name>.lock`, waiting for whoever holds it.
+/// Released when the returned file is dropped.
+pub fn lock_build(builds_dir: &Path, name: &str) -> Result<File, String> {
+    let locks = builds_dir.join(".locks");
+    fs::create_dir_all(&locks).map_err(|e| e.to_string())?;
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(locks.join(format!("{}.lock", name)))
+        .map_err(|e| e.to_string())?;
+
+    if file.try_lock_exclusive().is_err() {
+        outln!("{}", format!("⏳ Waiting for another build ({})...", name).yellow());
+        file.lock_exclusive().map_err(|e| e.to_string())?;
+    }
+
+    Ok(file)
+}
 
 /// Run a command quietly, but show output if error
 fn run_cmd(dir: &Path, cmd: &str, args: &[&str]) -> Result<(), String> {
     // println!("{}", format!("→ Running `{}`", [cmd, &args.join(" ")].join(" ")).blue());
     let output = Command::new(cmd)
         .args(args)
-        .env("NODE_OPTIONS", "--max-old-space-size=1024")
         .current_dir(dir)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -35,99 +147,132 @@ fn run_cmd(dir: &Path, cmd: &str, args: &[&str]) -> Result<(), String> {
     Ok(())
 }
 
-use urlencoding::encode;
-
-/// Run a git command with temporary credential injection
+/// Run a git command that may need to authenticate against origin
 fn run_git_with_auth(
     repo_path: &str,
     args: &[&str],
+    config: &Config,
 ) -> Result<(), String> {
-    let creds = get_git_credentials();
-
-    // Get original URL
-    let output = Command::new("git")
-        .args(&["config", "--get", "remote.origin.url"])
-        .current_dir(repo_path)
+    let result = authenticated_git(repo_path, config)?
+        .args(args)
         .output()
-        .map_err(|e| format!("Failed to get remote URL: {}", e))?;
+        .map_err(|e| format!("Git command failed: {}", e))?;
 
-    if !output.status.success() {
-        return Err(format!(
-            "Git config error: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
-    }
-    let original_url = String::from_utf8_lossy(&output.stdout).trim().to_string();
-
-    // Inject creds
-    let encoded_user = encode(&creds.username);
-    let encoded_pass = encode(&creds.password);
-    let temp_url = original_url.replace(
-        "https://",
-        &format!("https://{}:{}@", encoded_user, encoded_pass),
-    );
+    if !result.status.success() {
+        return Err(String::from_utf8_lossy(&result.stderr).to_string());
+    }
 
-    Command::new("git")
-        .args(&["remote", "set-url", "origin", &temp_url])
-        .current_dir(repo_path)
-        .output()
-        .map_err(|e| format!("Failed to set temp remote URL: {}", e))?;
+    Ok(())
+}
 
-    // Run actual git command
-    let result = Command::new("git")
-        .args(args)
-        .current_dir(repo_path)
-        .output()
-        .map_err(|e| format!("Git command failed: {}", e));
 
-    // Restore URL
-    Command::new("git")
-        .args(&["remote", "set-url", "origin", &original_url])
-        .current_dir(repo_path)
-        .output()
-        .map_err(|e| format!("Failed to restore remote URL: {}", e))?;
+/// Files a JS build depends on, unless the config lists its own
+pub fn default_build_inputs() -> Vec<String> {
+    [
+        "resources/js/**",
+        "resources/css/**",
+        "resources/**/*.css",
+        "package.json",
+        "pnpm-lock.yaml",
+        "package-lock.json",
+        "yarn.lock",
+        "vite.config.*",
+        "webpack.mix.js",
+        "tailwind.config.*",
+        "postcss.config.*",
+    ]
+    .iter()
+    .map(|g| g.to_string())
+    .collect()
+}
 
-    // Return error if git itself failed
-    match result {
-        Ok(res) if res.status.success() => Ok(()),
-        Ok(res) => Err(String::from_utf8_lossy(&res.stderr).to_string()),
-        Err(e) => Err(e),
-    }
+/// Build input globs of `project`: its own `build_inputs`, else the global ones
+pub fn build_inputs(project: &Path, config: &Config) -> Vec<String> {
+    config
+        .projects
+        .get(&project_name(project))
+        .and_then(|p| p.build_inputs.clone())
+        .unwrap_or_else(|| config.build_inputs.clone())
 }
 
+/// What a JS build is cached under
+#[derive(Debug, Clone)]
+pub struct BuildKey {
+    /// Hash over the build inputs, names the `.builds/<key>` directory
+    pub key: String,
+    /// Commit the inputs were read from, and that gets built
+    pub commit: String,
+    /// Path and git blob id of every input file
+    pub inputs: Vec<(String, String)>,
+    /// How it gets built, also part of the key
+    pub toolchain: Toolchain,
+}
 
-/// Get latest commit hash where `resources/js` changed
-fn latest_js_commit(project: &Path) -> Result<String, String> {
-    // find current branch name
-    let branch = Command::new("git")
-        .args(["rev-parse", "--abbrev-ref", "HEAD"])
-        .current_dir(project)
-        .output()
-        .map_err(|e| e.to_string())?;
-    if !branch.status.success() {
-        return Err("Failed to get branch".into());
-    }
-    let branch_name = String::from_utf8_lossy(&branch.stdout).trim().to_string();
-
-    // now check origin/<branch>
-    let output = Command::new("git")
-        .args([
-            "log",
-            "-n1",
-            "--pretty=format:%H",
-            &format!("origin/{}", branch_name),
-            "--",
-            "resources/js",
-        ])
+/// Hash the build inputs of `project` as of `rev`, without touching its working tree.
+/// The key covers the input globs and the content of every file they match,
+/// so any change to either gives a new build.
+pub fn build_key(project: &Path, rev: &str, config: &Config) -> Result<BuildKey, String> {
+    let name = project_name(project);
+    let globs = build_inputs(project, config);
+
+    let commit = run_git(project, &["rev-parse", &format!("{}^{{commit}}", rev)])?;
+
+    // `<mode> blob <id>\t<path>`
+    let blobs = run_git(project, &["ls-tree", "-r", "--full-tree", &commit])?
+        .lines()
+        .filter_map(|line| {
+            let (meta, path) = line.split_once('\t')?;
+            let id = meta.split_whitespace().nth(2)?;
+            Some((path.to_string(), id.to_string()))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let paths = blobs.keys().cloned().collect::<Vec<_>>();
+    let mut inputs = matching_paths(&globs, &paths)
+        .into_iter()
+        .map(|path| {
+            let id = blobs[&path].clone();
+            (path, id)
+        })
+        .collect::<Vec<_>>();
+    inputs.sort();
+
+    if inputs.is_empty() {
+        return Err(format!("No build inputs found in {} ({})", name, globs.join(", ")));
+    }
+
+    let toolchain = Toolchain::resolve(config, &name, |file| blobs.contains_key(file));
+
+    let mut content = globs.join("\n");
+    content.push_str("\n\n");
+    content.push_str(&toolchain.describe());
+    content.push_str("\n\n");
+    for (path, id) in &inputs {
+        content.push_str(&format!("{} {}\n", id, path));
+    }
+
+    let mut child = Command::new("git")
+        .args(["hash-object", "--stdin"])
         .current_dir(project)
-        .output()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
         .map_err(|e| e.to_string())?;
-
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content.as_bytes()).map_err(|e| e.to_string())?;
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
     if !output.status.success() {
-        return Err("Failed to get git log".into());
+        return Err(format!("Failed to hash build inputs: {}", String::from_utf8_lossy(&output.stderr).trim()));
     }
 
-    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    Ok(BuildKey {
+        key: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        commit,
+        inputs,
+        toolchain,
+    })
 }
 
 fn remove_any(path: &Path) {
@@ -139,77 +284,278 @@ fn remove_any(path: &Path) {
         }
     }
 }
+/// Where `public/build` currently points, if it is a symlink
+pub fn current_build_target(project: &Path) -> Option<PathBuf> {
+    fs::read_link(project.join("public/build")).ok()
+}
+
+/// Point `public/build` at `target`. A new symlink is renamed over the old
+/// one, so the site always serves either the old build or the new one.
+pub fn link_build(project: &Path, target: &Path) -> Result<(), String> {
+    let project_build_link = project.join("public/build");
+
+    // A build made in place can't be renamed over, move it into the cache first
+    if let Some(builds_dir) = target.parent() {
+        adopt_build_dir(project, builds_dir)?;
+    }
+
+    let temp_link = project.join(format!("public/.build.tmp-{}", std::process::id()));
+    let _ = fs::remove_file(&temp_link);
+    std::os::unix::fs::symlink(target, &temp_link).map_err(|e| e.to_string())?;
+
+    if let Err(e) = fs::rename(&temp_link, &project_build_link) {
+        let _ = fs::remove_file(&temp_link);
+        return Err(format!("Failed to switch {}: {}", project_build_link.display(), e));
+    }
+
+    Ok(())
+}
+
+/// If `public/build` is a real directory, e.g. from a `pnpm build` run by hand,
+/// move it into `builds_dir` as a `migrated-*` build and link it from there.
+/// Returns the build it was moved to.
+pub fn adopt_build_dir(project: &Path, builds_dir: &Path) -> Result<Option<PathBuf>, String> {
+    let project_build = project.join("public/build");
+    let Ok(metadata) = fs::symlink_metadata(&project_build) else {
+        return Ok(None);
+    };
+    if !metadata.is_dir() {
+        return Ok(None);
+    }
+
+    let name = format!(
+        "migrated-{}-{}",
+        project_name(project),
+        chrono::Local::now().format("%Y%m%d%H%M%S")
+    );
+    let target = builds_dir.join(&name);
+    outln!("{}", format!("📦 Moving public/build into the build cache as {}", name).yellow());
+
+    fs::create_dir_all(builds_dir).map_err(|e| e.to_string())?;
+    if fs::rename(&project_build, &target).is_err() {
+        // Different filesystem
+        copy_dir(&project_build, &target)?;
+        fs::remove_dir_all(&project_build).map_err(|e| e.to_string())?;
+    }
+
+    if let Err(e) = write_manifest(&target) {
+        outln!("{}", format!("⚠️  {} can't be verified: {}", name, e).yellow());
+    }
+    let finished = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    fs::write(target.join(BUILD_COMPLETE), finished).map_err(|e| e.to_string())?;
+
+    std::os::unix::fs::symlink(&target, &project_build).map_err(|e| e.to_string())?;
+    Ok(Some(target))
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<(), String> {
+    for entry in WalkDir::new(from) {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let relative = entry.path().strip_prefix(from).map_err(|e| e.to_string())?;
+        let dest = to.join(relative);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&dest).map_err(|e| e.to_string())?;
+        } else {
+            fs::copy(entry.path(), &dest).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
 /// Main workflow
-pub fn ensure_js_build(project: &Path, parent: &Path) -> Result<(), String> {
+pub fn ensure_js_build(project: &Path, parent: &Path, config: &Config) -> Result<(), String> {
     // println!("{}", "⚡ Starting JS build check".yellow().bold());
 
-    let commit_hash = latest_js_commit(project)?;
-    // println!("{} {}", "Latest JS commit:".green(), commit_hash);
+    let build = build_key(project, "HEAD", config)?;
+    let key = build.key.clone();
 
-    let builds_path = parent.join(".builds").join(&commit_hash);
-    let project_build_link = project.join("public/build");
+    let site = BuildSite::for_project(project, parent, config);
+    let builds_path = site.builds_dir.join(&key);
 
 
-    if builds_path.exists() && builds_path.read_dir().map_err(|e| e.to_string())?.next().is_some() {
-        // if project_build_link.exists() {
-        //     fs::remove_file(&project_build_link).ok();
-        // }
-        remove_any(&project_build_link);
-        
-        std::os::unix::fs::symlink(&builds_path, &project_build_link)
-            .map_err(|e| e.to_string())?;
-        println!("{}", "✅ Build already exists and has files, linked successfully".green());
+    match verify_build(&builds_path) {
+        Ok(()) => {
+            link_build(project, &builds_path)?;
+            outln!("{}", "✅ Build already exists and has files, linked successfully".green());
+            return Ok(());
+        },
+        Err(e) if builds_path.exists() => {
+            outln!("{}", format!("⚠️  Cached build {} failed verification ({}), rebuilding", key, e).yellow());
+        },
+        Err(_) => {},
+    }
+
+    // Waits while another project or process builds the same hash
+    let hash_lock = lock_build(&site.builds_dir, &key)?;
+
+    if verify_build(&builds_path).is_ok() {
+        drop(hash_lock);
+        link_build(project, &builds_path)?;
+        outln!("{}", "✅ Build already exists and has files, linked successfully".green());
         return Ok(());
     }
 
-    println!("{}", "⚠️  Build not found in parent, preparing build...".red());
+    // A retry of the project that failed builds again, everyone else gets its error
+    if let Some((failed_project, e)) = FAILED_BUILDS.lock().unwrap_or_else(|e| e.into_inner()).get(&builds_path)
+        && failed_project != project
+    {
+        return Err(format!("Build of {} already failed in this run: {}", key, e));
+    }
+
+    let result = build_in_site(&site, &build, &builds_path, config);
+    let mut failed_builds = FAILED_BUILDS.lock().unwrap_or_else(|e| e.into_inner());
+    match result {
+        Ok(()) => failed_builds.remove(&builds_path),
+        Err(e) => {
+            failed_builds.insert(builds_path.clone(), (project.to_path_buf(), e.clone()));
+            return Err(e);
+        },
+    };
+    drop(failed_builds);
+    drop(hash_lock);
+
+    // Link into project
+    link_build(project, &builds_path)?;
+
+    outln!("{}", "✅ Build linked successfully".green());
+    Ok(())
+}
+
+/// Build `commit` in a worktree of the site's repo into `builds_path`.
+/// The build goes to `.staging/<key>` first and is only moved into
+/// place, with its checksum manifest and completion marker, once it succeeded.
+fn build_in_site(site: &BuildSite, build: &BuildKey, builds_path: &Path, config: &Config) -> Result<(), String> {
+    let key = build.key.as_str();
+    outln!("{}", format!("⚠️  Build not found in {}, preparing build...", site.builds_dir.display()).red());
+
+    // Left over from a build that crashed
+    let staging = site.builds_dir.join(".staging").join(key);
+    remove_any(&staging);
+
+    let worktree = site.builds_dir.join(".worktrees").join(key);
+    add_worktree(site, &worktree, &build.commit, config)?;
+    let result = link_site_paths(&site.repo, &worktree, &build.toolchain.link_paths)
+        .and_then(|_| build_in_worktree(&site.builds_dir, &worktree, &staging, &build.toolchain));
+    if let Err(e) = remove_worktree(site, &worktree) {
+        outln!("{}", format!("⚠️  Failed to remove worktree {}: {}", worktree.display(), e).yellow());
+    }
+    if let Err(e) = result {
+        remove_any(&staging);
+        return Err(e);
+    }
+
+    if !staging.is_dir() {
+        return Err(format!("No {} directory produced by build", staging.display()));
+    }
+    if let Err(e) = write_manifest(&staging) {
+        remove_any(&staging);
+        return Err(e);
+    }
+    let finished = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    fs::write(staging.join(BUILD_COMPLETE), finished).map_err(|e| e.to_string())?;
+
+    // An incomplete or broken build of the same key never gets linked, replace it
+    if builds_path.exists() {
+        outln!("{}", format!("🗑️ Discarding old build: {}", key).red());
+        remove_any(builds_path);
+    }
+    fs::rename(&staging, builds_path).map_err(|e| e.to_string())?;
 
-    // Make sure commit exists in parent
+    Ok(())
+}
+
+/// Check out `commit_hash` into a fresh worktree of the site's repo, fetching it if needed
+fn add_worktree(site: &BuildSite, worktree: &Path, commit_hash: &str, config: &Config) -> Result<(), String> {
+    // Fetches and worktree bookkeeping in the repo's .git take turns
+    let _git_lock = lock_build(&site.builds_dir, "git")?;
+    let repo = site.repo.as_path();
+
+    // Make sure commit exists in the repo
     let check_commit = Command::new("git")
-        .args(["cat-file", "-t", &commit_hash])
-        .current_dir(parent)
+        .args(["cat-file", "-t", commit_hash])
+        .current_dir(repo)
         .output()
         .map_err(|e| e.to_string())?;
 
     if !check_commit.status.success() {
-        println!("{}", "⏬ Commit not found in parent, fetching...".blue());
-        run_git_with_auth(parent.to_str().unwrap(), &["fetch", "--all"])?;
+        outln!("{}", "⏬ Commit not found, fetching...".blue());
+        run_git_with_auth(&repo.to_string_lossy(), &["fetch", "--all"], config)?;
     }
 
-    // Checkout commit
-    // println!("{} {}", "🔀 Checking out commit".blue(), commit_hash);
-    run_cmd(parent, "git", &["checkout", &commit_hash])?;
+    // Left over from a build that crashed
+    if worktree.exists() {
+        remove_any(worktree);
+    }
+    run_cmd(repo, "git", &["worktree", "prune"])?;
 
-    // Install & build
-    println!("{}", "📦 Installing dependencies (pnpm install)...".yellow());
-    if let Err(e) = run_cmd(parent, "pnpm", &["install"]) {
-        return Err(format!("pnpm install failed: {}", e));
+    let path = worktree.to_string_lossy().to_string();
+    run_cmd(repo, "git", &["worktree", "add", "--detach", "--force", &path, commit_hash])
+}
+
+/// Symlink the untracked files of `repo` matching `patterns` into `worktree`,
+/// which only has the tracked ones: `.env` for Vite's `VITE_*` variables, `vendor`
+/// for aliases into it. Tracked files, e.g. `.env.example`, are left as checked out.
+fn link_site_paths(repo: &Path, worktree: &Path, patterns: &[String]) -> Result<(), String> {
+    for pattern in patterns {
+        let full = repo.join(pattern.trim_end_matches('/')).to_string_lossy().to_string();
+        let matches = glob::glob(&full).map_err(|e| format!("Invalid link path {}: {}", pattern, e))?;
+
+        for source in matches.filter_map(|m| m.ok()) {
+            let Ok(relative) = source.strip_prefix(repo) else {
+                continue;
+            };
+            let target = worktree.join(relative);
+            if fs::symlink_metadata(&target).is_ok() {
+                continue;
+            }
+            if let Some(dir) = target.parent() {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            std::os::unix::fs::symlink(&source, &target)
+                .map_err(|e| format!("Failed to link {} into the worktree: {}", relative.display(), e))?;
+        }
     }
+    Ok(())
+}
 
-    println!("{}", "🏗️  Building project...".yellow());
-    if let Err(e) = run_cmd(parent, "pnpm", &["vite", "build","--outDir",".dist"]) {
-        println!("{}", "❌ Build failed!".red());
-        return Err(e);
+/// Remove a build worktree and its registration in the site's repo
+fn remove_worktree(site: &BuildSite, worktree: &Path) -> Result<(), String> {
+    let _git_lock = lock_build(&site.builds_dir, "git")?;
+    remove_any(worktree);
+    run_cmd(&site.repo, "git", &["worktree", "prune"])
+}
+
+/// Install and build in `worktree`, writing the assets to `out_dir`
+fn build_in_worktree(builds_dir: &Path, worktree: &Path, out_dir: &Path, toolchain: &Toolchain) -> Result<(), String> {
+    // One store next to the builds, so every worktree hard links the same packages
+    let store = builds_dir.join(".pnpm-store").to_string_lossy().to_string();
+    let out = out_dir.to_string_lossy().to_string();
+    let placeholders = [("store", store.as_str()), ("out_dir", out.as_str())];
+
+    outln!("{}", format!("📦 Installing dependencies ({})...", toolchain.package_manager).yellow());
+    if let Err(e) = toolchain.run(worktree, &toolchain.install, &placeholders) {
+        return Err(format!("{} install failed: {}", toolchain.package_manager, e));
     }
 
-    // Move build output
-    let tmp_build = parent.join(".dist"); // vite default output
-    if tmp_build.exists() {
-        // println!("{}", "📂 Moving build to builds/{hash}".yellow());
-        fs::create_dir_all(&builds_path).ok();
-        fs::rename(&tmp_build, &builds_path).map_err(|e| e.to_string())?;
-    } else {
-        return Err("No .dist/ directory produced by build".into());
+    outln!("{}", "🏗️  Building project...".yellow());
+    if let Err(e) = toolchain.run(worktree, &toolchain.build, &placeholders) {
+        outln!("{}", "❌ Build failed!".red());
+        return Err(e);
     }
 
-    // Link into project
-    if project_build_link.exists() {
-        fs::remove_file(&project_build_link).ok();
+    // Build tools that can't be pointed at `out_dir` write into the project
+    if let Some(dir) = &toolchain.output_dir {
+        let produced = worktree.join(dir);
+        if !produced.is_dir() {
+            return Err(format!("No {} directory produced by build", dir));
+        }
+        remove_any(out_dir);
+        if let Some(staging) = out_dir.parent() {
+            fs::create_dir_all(staging).map_err(|e| e.to_string())?;
+        }
+        fs::rename(&produced, out_dir).map_err(|e| e.to_string())?;
     }
-    std::os::unix::fs::symlink(&builds_path, &project_build_link)
-        .map_err(|e| e.to_string())?;
 
-    println!("{}", "✅ Build linked successfully".green());
     Ok(())
 }
 
@@ -218,58 +564,80 @@ pub fn ensure_js_build(project: &Path, parent: &Path) -> Result<(), String> {
 
 
 
-pub fn cleanup_unused_parent_builds(projects: &[LaravelProject], parent: &Path) -> Result<(), String> {
+pub fn cleanup_unused_parent_builds(
+    projects: &[LaravelProject],
+    parent: &Path,
+    config: &Config,
+    state_manager: &StateManager,
+) -> Result<(), String> {
     // --- Only run once per day ---
     let parent_builds = parent.join(".builds");
     let lock_file = parent_builds.join(".cleanup_last_run");
-    if let Ok(metadata) = fs::metadata(&lock_file) {
-        if let Ok(mod_time) = metadata.modified() {
-            let now = SystemTime::now();
-            if let Ok(elapsed) = now.duration_since(mod_time) {
-                if elapsed.as_secs() < 24 * 60 * 60 {
-                    println!("{}", "⏱️  Cleanup already ran in the last 24h, skipping.".yellow());
-                    return Ok(());
-                }
-            }
-        }
+    if let Ok(metadata) = fs::metadata(&lock_file)
+        && let Ok(mod_time) = metadata.modified()
+        && let Ok(elapsed) = SystemTime::now().duration_since(mod_time)
+        && elapsed.as_secs() < 24 * 60 * 60
+    {
+        println!("{}", "⏱️  Cleanup already ran in the last 24h, skipping.".yellow());
+        return Ok(());
     }
 
     println!("{}", "🧹 Starting cleanup of unused parent builds...".blue());
 
-    // --- Collect all hashes currently used by projects ---
-    let mut used_hashes = Vec::new();
+    // --- The parent's builds, plus those of projects that build on their own ---
+    let mut sites = vec![BuildSite::shared(parent)];
     for project in projects {
-        let build_symlink = Path::new(&project.path).join("public/build");
-        if build_symlink.exists() {
-            if let Ok(target) = fs::read_link(&build_symlink) {
-                if let Some(hash_name) = target.file_name().map(|s| s.to_string_lossy().to_string()) {
-                    used_hashes.push(hash_name);
-                }
-            }
+        let site = BuildSite::for_project(Path::new(&project.path), parent, config);
+        if !sites.contains(&site) {
+            sites.push(site);
         }
     }
+    sites.retain(|site| site.builds_dir.exists());
 
-    // --- List all folders in parent builds ---
-    if !parent_builds.exists() {
+    if sites.is_empty() {
         println!("{}", "⚠️ Parent builds folder does not exist, nothing to clean.".yellow());
         return Ok(());
     }
 
-    for entry in fs::read_dir(parent_builds).map_err(|e| e.to_string())? {
-        let entry = entry.map_err(|e| e.to_string())?;
-        let path = entry.path();
-        if path.is_dir() {
-            let folder_name = path.file_name().unwrap().to_string_lossy();
-            if !used_hashes.contains(&folder_name.to_string()) {
-                println!("{}", format!("🗑️ Deleting unused build: {}", folder_name).red());
-                fs::remove_dir_all(&path).map_err(|e| e.to_string())?;
-            }
-        }
+    for site in &sites {
+        cleanup_site(site, projects, config, state_manager)?;
     }
 
     // --- Update the last run timestamp ---
+    fs::create_dir_all(&parent_builds).map_err(|e| e.to_string())?;
     fs::write(lock_file, b"done").map_err(|e| e.to_string())?;
 
     println!("{}", "✅ Cleanup completed".green());
     Ok(())
 }
+
+fn cleanup_site(
+    site: &BuildSite,
+    projects: &[LaravelProject],
+    config: &Config,
+    state_manager: &StateManager,
+) -> Result<(), String> {
+    // --- Everything the retention policy doesn't keep ---
+    let builds = list_builds(&site.builds_dir, projects, state_manager)?;
+    prune(&site.builds_dir, &builds, &config.build_retention, false);
+
+    // --- Staging dirs and worktrees of builds that crashed, nobody holds their lock ---
+    let leftovers = [".staging", ".worktrees"]
+        .iter()
+        .filter_map(|dir| fs::read_dir(site.builds_dir.join(dir)).ok())
+        .flatten()
+        .flatten();
+    for entry in leftovers {
+        let hash = entry.file_name().to_string_lossy().to_string();
+        let Ok(lock) = File::open(site.builds_dir.join(".locks").join(format!("{}.lock", hash))) else {
+            continue;
+        };
+        if lock.try_lock_exclusive().is_ok() {
+            println!("{}", format!("🗑️ Deleting incomplete build: {}", hash).red());
+            remove_any(&entry.path());
+        }
+    }
+    Command::new("git").args(["worktree", "prune"]).current_dir(&site.repo).output().ok();
+
+    Ok(())
+}
@@ -0,0 +1,476 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::time::SystemTime;
+
+use colored::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::advance::{is_complete_build, lock_build, BUILD_COMPLETE};
+use crate::git::short_hash;
+use crate::project::LaravelProject;
+use crate::state::StateManager;
+
+/// Which builds in `.builds` survive a prune
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Always keep this many of the newest builds
+    #[serde(default = "default_keep_last")]
+    pub keep_last: usize,
+    /// Keep builds younger than this many days
+    #[serde(default = "default_keep_days")]
+    pub keep_days: u64,
+    /// Keep builds a recorded deploy can roll back to
+    #[serde(default = "default_keep_deployed")]
+    pub keep_deployed: bool,
+}
+
+fn default_keep_last() -> usize {
+    5
+}
+
+fn default_keep_days() -> u64 {
+    7
+}
+
+fn default_keep_deployed() -> bool {
+    true
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            keep_last: default_keep_last(),
+            keep_days: default_keep_days(),
+            keep_deployed: default_keep_deployed(),
+        }
+    }
+}
+
+/// Checksums of a build's files, written when the build completes
+pub const BUILD_MANIFEST: &str = ".sahakari-manifest.json";
+
+/// Where Vite (depending on the version) and Laravel Mix put their manifest
+const ASSET_MANIFESTS: [&str; 3] = [".vite/manifest.json", "manifest.json", "mix-manifest.json"];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuildManifest {
+    pub created: String,
+    /// Relative path to sha256 of every file of the build
+    pub files: BTreeMap<String, String>,
+}
+
+fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).map_err(|e| e.to_string())?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Checksum every file of `build_dir`, except our own bookkeeping files
+fn checksum_files(build_dir: &Path) -> Result<BTreeMap<String, String>, String> {
+    let mut files = BTreeMap::new();
+    for entry in WalkDir::new(build_dir).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(build_dir)
+            .map_err(|e| e.to_string())?
+            .to_string_lossy()
+            .to_string();
+        if relative == BUILD_MANIFEST || relative == BUILD_COMPLETE {
+            continue;
+        }
+        files.insert(relative, sha256_file(entry.path())?);
+    }
+    Ok(files)
+}
+
+/// Record the checksums of a finished build in its manifest
+pub fn write_manifest(build_dir: &Path) -> Result<(), String> {
+    let files = checksum_files(build_dir)?;
+    if !ASSET_MANIFESTS.iter().any(|m| files.contains_key(*m)) {
+        return Err("Build has no Vite manifest.json or mix-manifest.json".to_string());
+    }
+
+    let manifest = BuildManifest {
+        created: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        files,
+    };
+    let json = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+    fs::write(build_dir.join(BUILD_MANIFEST), json).map_err(|e| e.to_string())
+}
+
+/// Check a build against its manifest: every file present with the recorded checksum,
+/// no symlinks and no files the manifest doesn't list
+pub fn verify_build(build_dir: &Path) -> Result<(), String> {
+    if !is_complete_build(build_dir) {
+        return Err("build is incomplete".to_string());
+    }
+
+    let content = fs::read_to_string(build_dir.join(BUILD_MANIFEST))
+        .map_err(|_| "build has no manifest".to_string())?;
+    let manifest: BuildManifest = serde_json::from_str(&content)
+        .map_err(|e| format!("invalid manifest: {}", e))?;
+
+    if !ASSET_MANIFESTS.iter().any(|m| manifest.files.contains_key(*m)) {
+        return Err("build has no Vite manifest.json or mix-manifest.json".to_string());
+    }
+
+    for entry in WalkDir::new(build_dir).min_depth(1) {
+        let entry = entry.map_err(|e| e.to_string())?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(build_dir)
+            .map_err(|e| e.to_string())?
+            .to_string_lossy()
+            .to_string();
+        if !entry.file_type().is_file() {
+            return Err(format!("{} is not a regular file", relative));
+        }
+        if relative != BUILD_MANIFEST && relative != BUILD_COMPLETE && !manifest.files.contains_key(&relative) {
+            return Err(format!("{} is not in the manifest", relative));
+        }
+    }
+
+    for (file, expected) in &manifest.files {
+        let path = build_dir.join(file);
+        // Not `is_file`, that follows symlinks
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_file() => {},
+            Ok(_) => return Err(format!("{} is not a regular file", file)),
+            Err(_) => return Err(format!("{} is missing", file)),
+        }
+        if &sha256_file(&path)? != expected {
+            return Err(format!("{} is corrupted", file));
+        }
+    }
+
+    Ok(())
+}
+
+/// One `.builds/<key>` directory
+#[derive(Debug, Clone)]
+pub struct BuildInfo {
+    pub key: String,
+    pub size: u64,
+    /// When the build finished, or the directory's time for incomplete builds
+    pub created: Option<SystemTime>,
+    pub complete: bool,
+    /// Projects whose `public/build` points here
+    pub linked_by: Vec<String>,
+    /// Projects that can roll back to this build
+    pub rollback_for: Vec<String>,
+}
+
+impl BuildInfo {
+    pub fn age_days(&self) -> Option<f64> {
+        let elapsed = SystemTime::now().duration_since(self.created?).ok()?;
+        Some(elapsed.as_secs_f64() / 86400.0)
+    }
+}
+
+fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+fn build_name(path: &Path) -> Option<String> {
+    path.file_name().map(|n| n.to_string_lossy().to_string())
+}
+
+/// All builds of the builds_dir, newest first
+pub fn list_builds(
+    builds_dir: &Path,
+    projects: &[LaravelProject],
+    state_manager: &StateManager,
+) -> Result<Vec<BuildInfo>, String> {
+    if !builds_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut builds = Vec::new();
+    for entry in fs::read_dir(builds_dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let Some(key) = build_name(&path) else {
+            continue;
+        };
+        // `.staging`, `.locks`, `.worktrees`, `.pnpm-store`
+        if !path.is_dir() || key.starts_with('.') {
+            continue;
+        }
+
+        let complete = is_complete_build(&path);
+        let created = if complete {
+            fs::metadata(path.join(BUILD_COMPLETE)).and_then(|m| m.modified()).ok()
+        } else {
+            fs::metadata(&path).and_then(|m| m.modified()).ok()
+        };
+
+        builds.push(BuildInfo {
+            key,
+            size: dir_size(&path),
+            created,
+            complete,
+            linked_by: Vec::new(),
+            rollback_for: Vec::new(),
+        });
+    }
+
+    for project in projects {
+        if let Ok(target) = fs::read_link(Path::new(&project.path).join("public/build"))
+            && target.parent() == Some(builds_dir)
+            && let Some(build) = build_name(&target).and_then(|key| builds.iter_mut().find(|b| b.key == key))
+        {
+            build.linked_by.push(project.name.clone());
+        }
+
+        for record in state_manager.deploy_history(&project.name) {
+            if let Some(previous) = record.previous_build
+                && Path::new(&previous).parent() == Some(builds_dir)
+                && let Some(build) = build_name(Path::new(&previous)).and_then(|key| builds.iter_mut().find(|b| b.key == key))
+                && !build.rollback_for.contains(&project.name)
+            {
+                build.rollback_for.push(project.name.clone());
+            }
+        }
+    }
+
+    builds.sort_by_key(|b| std::cmp::Reverse(b.created));
+    Ok(builds)
+}
+
+/// Why the build at `rank` (0 = newest) of `list_builds` is kept, `None` if a prune removes it
+pub fn keep_reason(build: &BuildInfo, rank: usize, policy: &RetentionPolicy) -> Option<String> {
+    if !build.linked_by.is_empty() {
+        return Some(format!("linked by {}", build.linked_by.join(", ")));
+    }
+    // Before the incomplete check, builds made before the completion marker
+    // existed are still valid rollback targets
+    if policy.keep_deployed && !build.rollback_for.is_empty() {
+        return Some(format!("rollback for {}", build.rollback_for.join(", ")));
+    }
+    if !build.complete {
+        return None;
+    }
+    if rank < policy.keep_last {
+        return Some(format!("one of the last {}", policy.keep_last));
+    }
+    if build.age_days().is_some_and(|age| age < policy.keep_days as f64) {
+        return Some(format!("younger than {} days", policy.keep_days));
+    }
+    None
+}
+
+/// Builds a prune would remove
+pub fn prune_candidates<'a>(builds: &'a [BuildInfo], policy: &RetentionPolicy) -> Vec<&'a BuildInfo> {
+    builds
+        .iter()
+        .enumerate()
+        .filter(|(rank, build)| keep_reason(build, *rank, policy).is_none())
+        .map(|(_, build)| build)
+        .collect()
+}
+
+/// The build whose key starts with `key`
+pub fn find_build<'a>(builds: &'a [BuildInfo], key: &str) -> Result<&'a BuildInfo, String> {
+    let matches = builds.iter().filter(|b| b.key.starts_with(key)).collect::<Vec<_>>();
+    match matches.as_slice() {
+        [build] => Ok(build),
+        [] => Err(format!("No build matches {}", key)),
+        _ => Err(format!("Ambiguous key {} matches several builds", key)),
+    }
+}
+
+/// Pack a verified build into a `.tar.zst` with a single `<key>/` directory
+pub fn export_build(builds_dir: &Path, key: &str, output: &Path) -> Result<u64, String> {
+    let build_dir = builds_dir.join(key);
+    verify_build(&build_dir).map_err(|e| format!("Build {} failed verification: {}", key, e))?;
+
+    let file = File::create(output).map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+    if let Err(e) = write_bundle(file, key, &build_dir) {
+        let _ = fs::remove_file(output);
+        return Err(e);
+    }
+
+    fs::metadata(output).map(|m| m.len()).map_err(|e| e.to_string())
+}
+
+fn write_bundle(file: File, key: &str, build_dir: &Path) -> Result<(), String> {
+    // Level 0 is zstd's default
+    let encoder = zstd::Encoder::new(file, 0).map_err(|e| e.to_string())?;
+    let mut archive = tar::Builder::new(encoder);
+    archive.follow_symlinks(false);
+    archive.append_dir_all(key, build_dir).map_err(|e| e.to_string())?;
+
+    let encoder = archive.into_inner().map_err(|e| e.to_string())?;
+    encoder.finish().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Unpack a bundle made by `export_build` into `.builds/<key>`, after verifying
+/// it against its manifest. Returns the key.
+pub fn import_build(builds_dir: &Path, input: &Path) -> Result<String, String> {
+    let file = File::open(input).map_err(|e| format!("Failed to open {}: {}", input.display(), e))?;
+    let decoder = zstd::Decoder::new(file).map_err(|e| e.to_string())?;
+
+    let unpack_dir = builds_dir
+        .join(".staging")
+        .join(format!(".import-{}", std::process::id()));
+    if unpack_dir.exists() {
+        fs::remove_dir_all(&unpack_dir).map_err(|e| e.to_string())?;
+    }
+    fs::create_dir_all(&unpack_dir).map_err(|e| e.to_string())?;
+
+    let result = unpack_bundle(builds_dir, decoder, &unpack_dir);
+    let _ = fs::remove_dir_all(&unpack_dir);
+    result
+}
+
+fn unpack_bundle(builds_dir: &Path, decoder: impl Read, unpack_dir: &Path) -> Result<String, String> {
+    // Only plain files and directories: a symlink could point anywhere on the server.
+    // `unpack_in` refuses entries that would land outside `unpack_dir`.
+    let mut archive = tar::Archive::new(decoder);
+    for entry in archive.entries().map_err(|e| format!("Invalid bundle: {}", e))? {
+        let mut entry = entry.map_err(|e| format!("Invalid bundle: {}", e))?;
+        let kind = entry.header().entry_type();
+        if !kind.is_file() && !kind.is_dir() {
+            let path = entry.path().map(|p| p.display().to_string()).unwrap_or_default();
+            return Err(format!("Invalid bundle: {} is not a regular file or directory", path));
+        }
+        entry.unpack_in(unpack_dir).map_err(|e| format!("Invalid bundle: {}", e))?;
+    }
+
+    let entries = fs::read_dir(unpack_dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(|e| e.ok())
+        .collect::<Vec<_>>();
+    let [entry] = entries.as_slice() else {
+        return Err("Invalid bundle: expected a single build directory".to_string());
+    };
+    let key = entry.file_name().to_string_lossy().to_string();
+    if key.starts_with('.') || !entry.path().is_dir() {
+        return Err(format!("Invalid bundle: unexpected entry {}", key));
+    }
+
+    verify_build(&entry.path()).map_err(|e| format!("Bundle failed verification: {}", e))?;
+
+    let build_dir = builds_dir.join(&key);
+    let _lock = lock_build(builds_dir, &key)?;
+    if verify_build(&build_dir).is_ok() {
+        return Err(format!("Build {} already exists", key));
+    }
+    if build_dir.exists() {
+        fs::remove_dir_all(&build_dir).map_err(|e| e.to_string())?;
+    }
+    fs::rename(entry.path(), &build_dir).map_err(|e| e.to_string())?;
+
+    Ok(key)
+}
+
+/// Delete `.builds/<key>`, waiting for a build of it to finish first
+pub fn remove_build(builds_dir: &Path, key: &str) -> Result<(), String> {
+    let path = builds_dir.join(key);
+    if key.starts_with('.') || key.contains('/') || !path.is_dir() {
+        return Err(format!("No build {}", key));
+    }
+
+    let _lock = lock_build(builds_dir, key)?;
+    fs::remove_dir_all(&path).map_err(|e| e.to_string())
+}
+
+/// Remove the builds the policy doesn't keep, returns the freed bytes
+pub fn prune(builds_dir: &Path, builds: &[BuildInfo], policy: &RetentionPolicy, dry_run: bool) -> u64 {
+    let mut freed = 0;
+    for build in prune_candidates(builds, policy) {
+        let what = if build.complete { "unused" } else { "incomplete" };
+        if dry_run {
+            println!("  {} Would delete {} build {} ({})", "!".yellow(), what, build.key, human_size(build.size));
+            freed += build.size;
+            continue;
+        }
+
+        match remove_build(builds_dir, &build.key) {
+            Ok(()) => {
+                println!("{}", format!("🗑️ Deleting {} build: {} ({})", what, build.key, human_size(build.size)).red());
+                freed += build.size;
+            },
+            Err(e) => println!("  {} Failed to delete {}: {}", "✗".red(), build.key, e),
+        }
+    }
+    freed
+}
+
+pub fn human_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+/// Print the builds as a table
+pub fn print_builds(builds: &[BuildInfo], policy: &RetentionPolicy) {
+    // Hashes are shortened, `migrated-*` names shown in full
+    let short_key = |build: &BuildInfo| -> String {
+        if build.key.starts_with("migrated-") {
+            build.key.clone()
+        } else {
+            short_hash(&build.key).to_string()
+        }
+    };
+    let width = builds.iter().map(|b| short_key(b).len() + 2).max().unwrap_or(0).max(14);
+
+    println!(
+        "{:<width$}  {:>9}  {:>7}  {}",
+        "Key".bold(),
+        "Size".bold(),
+        "Age".bold(),
+        "Used by".bold()
+    );
+    println!("{}", "─".repeat(width + 56));
+
+    for (rank, build) in builds.iter().enumerate() {
+        let key = short_key(build);
+        let age = build
+            .age_days()
+            .map(|d| if d < 1.0 { format!("{:.0}h", d * 24.0) } else { format!("{:.0}d", d) })
+            .unwrap_or_else(|| "?".to_string());
+        let used = match keep_reason(build, rank, policy) {
+            Some(reason) => reason.normal(),
+            None if build.complete => "unused, pruned next time".yellow(),
+            None => "incomplete".red(),
+        };
+        println!("{:<width$}  {:>9}  {:>7}  {}", key.cyan(), human_size(build.size), age, used);
+    }
+
+    let total = builds.iter().map(|b| b.size).sum::<u64>();
+    println!("\n{} build(s), {}", builds.len(), human_size(total));
+}
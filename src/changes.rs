@@ -0,0 +1,59 @@
+use crate::pipeline::{matching_paths, Builtin, Pipeline, Trigger};
+
+/// Changed files of an update, grouped by the pipeline step they trigger, the rest by type
+#[derive(Debug, Clone, Default)]
+pub struct ChangeSet {
+    /// Step name and its files, in pipeline order
+    pub steps: Vec<(String, Vec<String>)>,
+    /// PHP files no step reacts to, application code that changes without a step
+    pub php: Vec<String>,
+    /// Everything else no step reacts to
+    pub other: Vec<String>,
+}
+
+impl ChangeSet {
+    /// Group `files` by the path globs of the steps of `pipeline`. The JS build
+    /// step is matched against `build_inputs`, the files its cache key covers.
+    /// A file can trigger several steps and is listed under each of them.
+    pub fn classify(files: &[String], pipeline: &Pipeline, build_inputs: &[String]) -> Self {
+        let mut set = ChangeSet::default();
+
+        for step in &pipeline.steps {
+            let matched = match (&step.builtin, &step.when) {
+                (Some(Builtin::JsBuild), _) => matching_paths(build_inputs, files),
+                (_, Trigger::Paths(_)) => step.matching_files(files),
+                _ => continue,
+            };
+            set.steps.push((step.name.clone(), matched));
+        }
+
+        let (php, other) = files
+            .iter()
+            .filter(|file| !set.steps.iter().any(|(_, matched)| matched.contains(file)))
+            .cloned()
+            .partition(|file| file.ends_with(".php"));
+        set.php = php;
+        set.other = other;
+
+        set
+    }
+
+    /// Files that trigger the step `name`
+    pub fn for_step(&self, name: &str) -> Vec<String> {
+        self.steps
+            .iter()
+            .find(|(step, _)| step == name)
+            .map(|(_, files)| files.clone())
+            .unwrap_or_default()
+    }
+
+    /// Groups with at least one file, in display order
+    pub fn groups(&self) -> Vec<(&str, &Vec<String>)> {
+        self.steps
+            .iter()
+            .map(|(name, files)| (name.as_str(), files))
+            .chain([("php", &self.php), ("other", &self.other)])
+            .filter(|(_, files)| !files.is_empty())
+            .collect()
+    }
+}
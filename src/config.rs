@@ -1,13 +1,68 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::io;
 use std::path::Path;
 
+use crate::advance::{default_build_inputs, BuildMode};
+use crate::builds::RetentionPolicy;
+use crate::credentials::CredentialsConfig;
+use crate::git::LocalChangesPolicy;
+use crate::pipeline::OnError;
+use crate::ssh::SshConfig;
+use crate::toolchain::ToolchainConfig;
+
+/// Settings for a single project, keyed by its folder name
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct ProjectConfig {
+    /// Private key used for ssh remotes of this project
+    #[serde(default)]
+    pub deploy_key: Option<String>,
+    /// Overrides the global `local_changes` policy
+    #[serde(default)]
+    pub local_changes: Option<LocalChangesPolicy>,
+    /// Branch, tag or commit this project is deployed from instead of its checked out branch
+    #[serde(default)]
+    pub pinned_ref: Option<String>,
+    /// Overrides the global `build_inputs`
+    #[serde(default)]
+    pub build_inputs: Option<Vec<String>>,
+    /// Overrides fields of the global `toolchain`
+    #[serde(default)]
+    pub toolchain: Option<ToolchainConfig>,
+    /// Build from the shared parent (default), or from the project itself
+    #[serde(default)]
+    pub build_mode: BuildMode,
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Config {
     pub projects_root: String,
     pub log_path: String,
     pub state_path: String,
+    /// Where git credentials come from, see `credentials.rs`
+    #[serde(default)]
+    pub credentials: CredentialsConfig,
+    /// Deploy keys and host key handling for ssh remotes
+    #[serde(default)]
+    pub ssh: SshConfig,
+    #[serde(default)]
+    pub projects: HashMap<String, ProjectConfig>,
+    /// What to do with uncommitted changes on a deploy target before pulling
+    #[serde(default)]
+    pub local_changes: LocalChangesPolicy,
+    /// Default for `update --on-error`, unset means ask (or abort without a terminal)
+    #[serde(default)]
+    pub on_error: Option<OnError>,
+    /// Globs of the files a JS build depends on, hashed into the build cache key
+    #[serde(default = "default_build_inputs")]
+    pub build_inputs: Vec<String>,
+    /// Which JS builds survive the daily cleanup and `builds prune`
+    #[serde(default)]
+    pub build_retention: RetentionPolicy,
+    /// Package manager and commands used to build JS assets
+    #[serde(default)]
+    pub toolchain: ToolchainConfig,
 }
 
 impl Config {
@@ -24,6 +79,14 @@ impl Config {
             projects_root: "/var/www/html".to_string(),
             log_path: config_dir.join("logs").to_string_lossy().to_string(),
             state_path: config_dir.join("state").to_string_lossy().to_string(),
+            credentials: CredentialsConfig::default(),
+            ssh: SshConfig::default(),
+            projects: HashMap::new(),
+            local_changes: LocalChangesPolicy::default(),
+            on_error: None,
+            build_inputs: default_build_inputs(),
+            build_retention: RetentionPolicy::default(),
+            toolchain: ToolchainConfig::default(),
         }
     }
     
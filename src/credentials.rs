@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use inquire::{Password, Text};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone)]
+pub struct GitCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Where git credentials can be read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CredentialSource {
+    /// `SAHAKARI_GIT_USERNAME` / `SAHAKARI_GIT_PASSWORD`, optionally suffixed with the host
+    Env,
+    /// `~/.sahakari/credentials.json`, must only be readable by its owner
+    File,
+    /// `git credential fill`, i.e. whatever helper git itself is configured with
+    GitHelper,
+    /// Ask on the terminal
+    Prompt,
+}
+
+impl std::fmt::Display for CredentialSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            CredentialSource::Env => "env",
+            CredentialSource::File => "file",
+            CredentialSource::GitHelper => "git-helper",
+            CredentialSource::Prompt => "prompt",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Overrides for a single remote host
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HostCredentials {
+    /// Sources to try for this host, in order. Falls back to the global list.
+    #[serde(default)]
+    pub sources: Option<Vec<CredentialSource>>,
+    /// Account to use on this host
+    #[serde(default)]
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialsConfig {
+    /// Sources tried in order until one yields credentials
+    #[serde(default = "default_sources")]
+    pub sources: Vec<CredentialSource>,
+    /// Per remote host overrides, keyed by host name (e.g. `github.com`)
+    #[serde(default)]
+    pub hosts: HashMap<String, HostCredentials>,
+}
+
+fn default_sources() -> Vec<CredentialSource> {
+    vec![
+        CredentialSource::Env,
+        CredentialSource::File,
+        CredentialSource::GitHelper,
+        CredentialSource::Prompt,
+    ]
+}
+
+impl Default for CredentialsConfig {
+    fn default() -> Self {
+        CredentialsConfig {
+            sources: default_sources(),
+            hosts: HashMap::new(),
+        }
+    }
+}
+
+/// One account stored in the credentials file
+#[derive(Debug, Clone, Deserialize)]
+struct StoredCredential {
+    /// Host this account belongs to, `None` matches any host
+    #[serde(default)]
+    host: Option<String>,
+    username: String,
+    password: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CredentialsFile {
+    #[serde(default)]
+    accounts: Vec<StoredCredential>,
+}
+
+static CACHED_CREDENTIALS: Lazy<Mutex<HashMap<String, GitCredentials>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn credentials_file_path() -> PathBuf {
+    let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
+    home.join(".sahakari/credentials.json")
+}
+
+/// Resolve credentials for `host`, trying each configured source in order.
+/// Results are cached per host for the lifetime of the process.
+pub fn get_git_credentials(config: &CredentialsConfig, host: &str) -> Result<GitCredentials, String> {
+    if let Some(cached) = CACHED_CREDENTIALS.lock().unwrap().get(host) {
+        return Ok(cached.clone());
+    }
+
+    let host_config = config.hosts.get(host).cloned().unwrap_or_default();
+    let sources = host_config.sources.as_ref().unwrap_or(&config.sources);
+    let username = host_config.username.as_deref();
+
+    let mut errors = Vec::new();
+    for source in sources {
+        let found = match source {
+            CredentialSource::Env => from_env(host),
+            CredentialSource::File => from_file(host, username),
+            CredentialSource::GitHelper => from_git_helper(host, username),
+            CredentialSource::Prompt => from_prompt(host, username),
+        };
+
+        match found {
+            Ok(Some(creds)) => {
+                CACHED_CREDENTIALS
+                    .lock()
+                    .unwrap()
+                    .insert(host.to_string(), creds.clone());
+                return Ok(creds);
+            }
+            Ok(None) => {}
+            Err(e) => errors.push(format!("{}: {}", source, e)),
+        }
+    }
+
+    let tried = sources.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(", ");
+    if errors.is_empty() {
+        Err(format!("No git credentials found for {} (tried: {})", host, tried))
+    } else {
+        Err(format!(
+            "No git credentials found for {} (tried: {}): {}",
+            host,
+            tried,
+            errors.join("; ")
+        ))
+    }
+}
+
+/// Host name as an environment variable suffix, `git.example.com` -> `GIT_EXAMPLE_COM`
+fn env_suffix(host: &str) -> String {
+    host.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect()
+}
+
+fn from_env(host: &str) -> Result<Option<GitCredentials>, String> {
+    let suffix = env_suffix(host);
+    let read = |name: &str| {
+        std::env::var(format!("{}_{}", name, suffix))
+            .or_else(|_| std::env::var(name))
+            .ok()
+            .filter(|v| !v.is_empty())
+    };
+
+    match (read("SAHAKARI_GIT_USERNAME"), read("SAHAKARI_GIT_PASSWORD")) {
+        (Some(username), Some(password)) => Ok(Some(GitCredentials { username, password })),
+        _ => Ok(None),
+    }
+}
+
+fn from_file(host: &str, username: Option<&str>) -> Result<Option<GitCredentials>, String> {
+    let path = credentials_file_path();
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let metadata = fs::metadata(&path).map_err(|e| e.to_string())?;
+    if metadata.permissions().mode() & 0o077 != 0 {
+        return Err(format!(
+            "{} is readable by other users, run `chmod 600` on it",
+            path.display()
+        ));
+    }
+
+    let content = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let file: CredentialsFile = serde_json::from_str(&content)
+        .map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+
+    let matches_user = |c: &&StoredCredential| username.is_none_or(|u| c.username == u);
+
+    // An account for this exact host wins over a catch-all one
+    let found = file
+        .accounts
+        .iter()
+        .filter(matches_user)
+        .find(|c| c.host.as_deref() == Some(host))
+        .or_else(|| file.accounts.iter().filter(matches_user).find(|c| c.host.is_none()));
+
+    Ok(found.map(|c| GitCredentials {
+        username: c.username.clone(),
+        password: c.password.clone(),
+    }))
+}
+
+fn from_git_helper(host: &str, username: Option<&str>) -> Result<Option<GitCredentials>, String> {
+    let mut child = Command::new("git")
+        .args(["credential", "fill"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    let mut request = format!("protocol=https\nhost={}\n", host);
+    if let Some(user) = username {
+        request.push_str(&format!("username={}\n", user));
+    }
+    request.push('\n');
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+    }
+
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    // git exits non-zero when no helper knows the host and prompting is disabled
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let mut found_user = None;
+    let mut found_pass = None;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(v) = line.strip_prefix("username=") {
+            found_user = Some(v.to_string());
+        } else if let Some(v) = line.strip_prefix("password=") {
+            found_pass = Some(v.to_string());
+        }
+    }
+
+    match (found_user, found_pass) {
+        (Some(username), Some(password)) => Ok(Some(GitCredentials { username, password })),
+        _ => Ok(None),
+    }
+}
+
+fn from_prompt(host: &str, username: Option<&str>) -> Result<Option<GitCredentials>, String> {
+    crate::output::interactive(|| ask_credentials(host, username))
+}
+
+fn ask_credentials(host: &str, username: Option<&str>) -> Result<Option<GitCredentials>, String> {
+    let username = match username {
+        Some(u) => u.to_string(),
+        None => Text::new(&format!("Git username for {}:", host))
+            .prompt()
+            .map_err(|e| e.to_string())?,
+    };
+
+    let password = Password::new(&format!("Git password for {}@{}:", username, host))
+        .without_confirmation()
+        .prompt()
+        .map_err(|e| e.to_string())?;
+
+    Ok(Some(GitCredentials { username, password }))
+}
@@ -1,185 +1,704 @@
-use std::io::{self, Write};
-use std::process::Command;
 use std::path::Path;
-use urlencoding::encode;
-use once_cell::sync::OnceCell;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::credentials::get_git_credentials;
+use crate::ssh::{git_ssh_command, is_ssh_url};
+
 pub struct GitOperations;
 
-#[derive(Debug, Clone)]
-pub struct GitCredentials {
-    pub username: String,
-    pub password: String,
-}
-
-static CACHED_CREDENTIALS: OnceCell<GitCredentials> = OnceCell::new();
-
-pub fn get_git_credentials() -> GitCredentials {
-    if let Some(cached) = CACHED_CREDENTIALS.get() {
-        return cached.clone();
-    }
-    //
-    // print!("Git Username: ");
-    // io::stdout().flush().unwrap();
-    // let mut username = String::new();
-    // io::stdin().read_line(&mut username).unwrap();
-    //
-    // print!("Git Password: ");
-    // io::stdout().flush().unwrap();
-    // let mut password = String::new();
-    // io::stdin().read_line(&mut password).unwrap();
-    //
-    let creds = GitCredentials {
-        username: "brightsoftware.backup@gmail.com".to_string(),
-        password: "brightreadonlybot".to_string(),
+/// Extract the host name from a remote URL.
+/// Handles `https://user@host:port/path`, `ssh://git@host/path` and scp-like `git@host:path`.
+pub fn remote_host(url: &str) -> Option<String> {
+    let authority = match url.split_once("://") {
+        Some((_, rest)) => rest.split('/').next().unwrap_or(""),
+        // scp-like syntax, the path starts after the first ':'
+        None => url.split(':').next().unwrap_or(""),
     };
 
-    // Store once
-    CACHED_CREDENTIALS.set(creds.clone()).ok();
-    creds
+    // Userinfo ends at the last '@', an optional port follows the host
+    let host = authority.rsplit('@').next().unwrap_or("");
+    let host = host.split(':').next().unwrap_or("");
+    if host.is_empty() {
+        None
+    } else {
+        Some(host.to_string())
+    }
 }
 
-impl GitOperations {
-
-    
+/// Get the URL of `remote` in `repo_path`
+pub fn remote_url(repo_path: &str, remote: &str) -> Result<String, String> {
+    let output = Command::new("git")
+        .args(["config", "--get", &format!("remote.{}.url", remote)])
+        .current_dir(repo_path)
+        .output()
+        .map_err(|e| format!("Failed to get remote URL: {}", e))?;
 
+    if !output.status.success() {
+        return Err(format!("Git config error: {}", String::from_utf8_lossy(&output.stderr)));
+    }
 
-pub fn pull(repo_path: &str) -> Result<Vec<String>, String> {
-   
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
 
-    let creds = get_git_credentials();
+/// Remove `user:password@` from an http(s) URL.
+/// Returns `None` when the URL carries no credentials.
+pub fn strip_url_credentials(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    if scheme != "http" && scheme != "https" {
+        return None;
+    }
 
-    // Step 1: Auto-commit any local changes (empty message, optional)
-    let _ = Command::new("git")
-        .args(&["commit", "-am", "auto-commit before pull"])
-        .current_dir(repo_path)
-        .output(); // Ignored errors — it might just mean "nothing to commit"
+    let (authority, path) = match rest.split_once('/') {
+        Some((authority, path)) => (authority, Some(path)),
+        None => (rest, None),
+    };
+    let (_, host) = authority.rsplit_once('@')?;
 
-    // Step 2: Get HEAD before pull
-    let before = Command::new("git")
-        .args(&["rev-parse", "HEAD"])
-        .current_dir(repo_path)
-        .output()
-        .map_err(|e| format!("Failed to get HEAD before pull: {}", e))?;
+    Some(match path {
+        Some(path) => format!("{}://{}/{}", scheme, host, path),
+        None => format!("{}://{}", scheme, host),
+    })
+}
 
-    if !before.status.success() {
-        let err = String::from_utf8_lossy(&before.stderr);
-        return Err(format!("Failed to get HEAD before pull: {}", err));
+/// A `git` command in `repo_path` that can authenticate against origin.
+///
+/// For http(s) remotes the credentials are passed through a one-off
+/// `credential.helper` that reads them from the child's environment, so
+/// nothing is ever written to `.git/config` and the password never shows
+/// up in the process list. For ssh remotes `GIT_SSH_COMMAND` selects the
+/// project's deploy key and the tool's known_hosts file.
+pub fn authenticated_git(repo_path: &str, config: &Config) -> Result<Command, String> {
+    let url = remote_url(repo_path, "origin")?;
+    let project = Path::new(repo_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut cmd = Command::new("git");
+    cmd.current_dir(repo_path).env("GIT_TERMINAL_PROMPT", "0");
+
+    if url.starts_with("https://") || url.starts_with("http://") {
+        let host = remote_host(&url)
+            .ok_or_else(|| format!("Cannot determine host of remote {}", url))?;
+        let creds = get_git_credentials(&config.credentials, &host)?;
+
+        cmd.env("SAHAKARI_GIT_USER", &creds.username)
+            .env("SAHAKARI_GIT_PASS", &creds.password)
+            // The empty helper resets any helpers configured globally
+            .args(["-c", "credential.helper="])
+            .args([
+                "-c",
+                "credential.helper=!f() { test \"$1\" = get || exit 0; echo \"username=$SAHAKARI_GIT_USER\"; echo \"password=$SAHAKARI_GIT_PASS\"; }; f",
+            ]);
+    } else if is_ssh_url(&url) {
+        let host = remote_host(&url)
+            .ok_or_else(|| format!("Cannot determine host of remote {}", url))?;
+        cmd.env("GIT_SSH_COMMAND", git_ssh_command(config, &host, &project)?);
     }
 
-    let before_hash = String::from_utf8_lossy(&before.stdout).trim().to_string();
+    Ok(cmd)
+}
 
+/// What to do with uncommitted changes found in a project before pulling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LocalChangesPolicy {
+    /// Leave the project untouched and report the changed files
+    #[default]
+    Abort,
+    /// `git stash` before pulling and re-apply the changes afterwards
+    Stash,
+    /// Throw the changes away and reset to the remote branch
+    Reset,
+    /// Commit the changes on a `sahakari/local-*` branch, never on the deployed one
+    SideBranch,
+}
 
-    //get branch name to pull that git rev-parse --abbrev-ref HEAD
-    let branch = Command::new("git")
-            .args(&["rev-parse", "--abbrev-ref", "HEAD"])
-            .current_dir(repo_path)
-            .output()
-            .map_err(|e| format!("Git pull failed: {}", e))?;
+impl std::fmt::Display for LocalChangesPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            LocalChangesPolicy::Abort => "abort",
+            LocalChangesPolicy::Stash => "stash",
+            LocalChangesPolicy::Reset => "reset",
+            LocalChangesPolicy::SideBranch => "side-branch",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How the local branch relates to `origin/<branch>` after a fetch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncStatus {
+    UpToDate,
+    /// Remote has `behind` new commits and none are missing locally
+    FastForward { behind: usize },
+    /// Local has commits the remote doesn't, nothing to pull
+    Ahead { ahead: usize },
+    /// Both sides have commits of their own, `git log --oneline` style lists
+    Diverged {
+        local_commits: Vec<String>,
+        remote_commits: Vec<String>,
+    },
+    /// Checked out a different branch, tag or commit
+    Switched { to: String },
+}
 
-        if !branch.status.success() {
-            let err = String::from_utf8_lossy(&branch.stderr);
-            return Err(format!("Git get branch failed: {}", err));
+impl std::fmt::Display for SyncStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SyncStatus::UpToDate => write!(f, "up to date"),
+            SyncStatus::FastForward { behind } => write!(f, "fast-forward ({} new commit(s))", behind),
+            SyncStatus::Ahead { ahead } => write!(f, "ahead of remote by {} commit(s)", ahead),
+            SyncStatus::Diverged { local_commits, remote_commits } => write!(
+                f,
+                "diverged ({} local, {} remote commit(s))",
+                local_commits.len(),
+                remote_commits.len()
+            ),
+            SyncStatus::Switched { to } => write!(f, "switched to {}", to),
         }
+    }
+}
 
-    let branch_name = String::from_utf8_lossy(&branch.stdout).trim().to_string();
+/// Outcome of a successful pull
+#[derive(Debug, Clone)]
+pub struct PullResult {
+    pub sync: SyncStatus,
+    pub changed_files: Vec<String>,
+    /// Tracked files that had uncommitted changes before the pull
+    pub dirty_files: Vec<String>,
+    /// What was done with those changes, e.g. the side branch they were saved to
+    pub local_changes_note: Option<String>,
+}
 
+/// A commit that an update would bring in
+#[derive(Debug, Clone)]
+pub struct CommitInfo {
+    pub hash: String,
+    pub author: String,
+    pub subject: String,
+}
 
-    /*
-    |--------------------------------------------------------------------------
-    | Set password and username to make sure we dont have to login again and again
-    |--------------------------------------------------------------------------
-    |
-    */
-       // Step 1: Get original remote URL
+/// What updating to `target` would change
+#[derive(Debug, Clone)]
+pub struct Incoming {
+    /// Revision the project would move to, e.g. `origin/main` or a tag
+    pub target: String,
+    /// What the update would do: move HEAD (fast-forward or switch) or leave it
+    pub sync: SyncStatus,
+    pub commits: Vec<CommitInfo>,
+    pub changed_files: Vec<String>,
+    /// Locally modified files that make the update abort under the `abort` policy
+    pub aborting_changes: Vec<String>,
+}
+
+/// First 10 characters of a commit, blob or build hash, for display
+pub fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(10)]
+}
+
+/// Run git in `repo_path` and return its stdout without the trailing newline
+pub(crate) fn run_git(repo_path: impl AsRef<Path>, args: &[&str]) -> Result<String, String> {
     let output = Command::new("git")
-        .args(&["config", "--get", "remote.origin.url"])
+        .args(args)
         .current_dir(repo_path)
         .output()
-        .map_err(|e| format!("Failed to get remote URL: {}", e))?;
+        .map_err(|e| format!("git {} failed: {}", args.join(" "), e))?;
 
     if !output.status.success() {
-        return Err(format!("Git config error: {}", String::from_utf8_lossy(&output.stderr)));
+        let err = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("git {} failed: {}", args.join(" "), err.trim()));
     }
 
-    let original_url = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok(String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+}
 
-   
-    // Step 3: Encode credentials
-    let encoded_user = encode(&creds.username);
-    let encoded_pass = encode(&creds.password);
+impl GitOperations {
 
+/// Commit currently checked out
+pub fn head(repo_path: &str) -> Result<String, String> {
+    run_git(repo_path, &["rev-parse", "HEAD"])
+}
 
-    let temp_url = original_url.replace(
-            "https://",
-            &format!("https://{}:{}@", encoded_user, encoded_pass),
-        );
+/// Checked out branch, `None` when HEAD is detached
+pub fn current_branch(repo_path: &str) -> Result<Option<String>, String> {
+    let branch = run_git(repo_path, &["rev-parse", "--abbrev-ref", "HEAD"])?;
+    Ok(if branch == "HEAD" { None } else { Some(branch) })
+}
 
+/// Put HEAD back on `commit`, on `branch` if given, keeping uncommitted changes
+pub fn restore(repo_path: &str, branch: Option<&str>, commit: &str) -> Result<(), String> {
+    match branch {
+        Some(branch) => {
+            if Self::current_branch(repo_path)?.as_deref() != Some(branch) {
+                run_git(repo_path, &["checkout", branch])?;
+            }
+            run_git(repo_path, &["reset", "--keep", commit])?;
+        }
+        None => {
+            run_git(repo_path, &["checkout", "--detach", commit])?;
+        }
+    }
+    Ok(())
+}
 
-    Command::new("git")
-        .args(&["remote", "set-url", "origin", &temp_url])
-        .current_dir(repo_path)
-        .output()
-        .map_err(|e| format!("Failed to set remote URL: {}", e))?;
+/// Tracked files with uncommitted changes. Untracked files (uploads, .env, ...) are ignored.
+pub fn dirty_files(repo_path: &str) -> Result<Vec<String>, String> {
+    let status = run_git(repo_path, &["status", "--porcelain", "--untracked-files=no"])?;
+    Ok(status
+        .lines()
+        .filter(|l| l.len() > 3)
+        .map(|l| l[3..].to_string())
+        .collect())
+}
 
-    // Step 3: git pull
-    let pull = Command::new("git")
-        .args(&["pull","origin",&branch_name])
-        .current_dir(repo_path)
+/// Fetch `branch` from origin
+pub fn fetch(repo_path: &str, branch: &str, config: &Config) -> Result<(), String> {
+    let fetch = authenticated_git(repo_path, config)?
+        .args(["fetch", "origin", branch])
         .output()
-        .map_err(|e| format!("Git pull failed: {}", e))?;
+        .map_err(|e| format!("Git fetch failed: {}", e))?;
 
-    Command::new("git")
-        .args(&["remote", "set-url", "origin", &original_url])
-        .current_dir(repo_path)
-        .output()
-        .map_err(|e| format!("Failed to reset remote URL: {}", e))?;
+    if !fetch.status.success() {
+        return Err(format!("Git fetch failed: {}", String::from_utf8_lossy(&fetch.stderr)));
+    }
 
+    Ok(())
+}
+
+/// Compare HEAD with `origin/<branch>`, call after `fetch`
+pub fn sync_status(repo_path: &str, branch: &str) -> Result<SyncStatus, String> {
+    Self::sync_status_of(repo_path, "HEAD", branch)
+}
+
+/// Like `sync_status`, for `local` (a branch or commit) instead of HEAD
+fn sync_status_of(repo_path: &str, local: &str, branch: &str) -> Result<SyncStatus, String> {
+    let remote = format!("origin/{}", branch);
+    let counts = run_git(repo_path, &["rev-list", "--left-right", "--count", &format!("{}...{}", local, remote)])?;
+    let mut counts = counts.split_whitespace().map(|n| n.parse::<usize>().unwrap_or(0));
+    let ahead = counts.next().unwrap_or(0);
+    let behind = counts.next().unwrap_or(0);
+
+    Ok(match (ahead, behind) {
+        (0, 0) => SyncStatus::UpToDate,
+        (0, behind) => SyncStatus::FastForward { behind },
+        (ahead, 0) => SyncStatus::Ahead { ahead },
+        _ => {
+            let list = |range: &str| -> Result<Vec<String>, String> {
+                Ok(run_git(repo_path, &["log", "--format=%h %s (%an)", range])?
+                    .lines()
+                    .map(|l| l.to_string())
+                    .collect())
+            };
+            SyncStatus::Diverged {
+                local_commits: list(&format!("{}..{}", remote, local))?,
+                remote_commits: list(&format!("{}..{}", local, remote))?,
+            }
+        }
+    })
+}
 
-    if !pull.status.success() {
-        let err = String::from_utf8_lossy(&pull.stderr);
-        return Err(format!("Git pull failed: {}", err));
+/// Fetch and fast-forward the checked out branch to `origin/<branch>`.
+/// Projects that are ahead or have diverged are left alone, check `PullResult::sync`.
+pub fn pull(repo_path: &str, config: &Config) -> Result<PullResult, String> {
+    let policy = Self::local_changes_policy(repo_path, config);
+
+    let branch_name = run_git(repo_path, &["rev-parse", "--abbrev-ref", "HEAD"])?;
+    if branch_name == "HEAD" {
+        return Err("HEAD is detached, pass --ref or pin a ref for this project".to_string());
     }
 
-    
+    // Step 1: Fetch and find out whether we can fast-forward at all
+    Self::fetch(repo_path, &branch_name, config)?;
+    let sync = Self::sync_status(repo_path, &branch_name)?;
+    let dirty_files = Self::dirty_files(repo_path)?;
+
+    if !matches!(sync, SyncStatus::FastForward { .. }) {
+        let local_changes_note = if dirty_files.is_empty() {
+            None
+        } else {
+            Some("left untouched, nothing to pull".to_string())
+        };
+        return Ok(PullResult {
+            sync,
+            changed_files: Vec::new(),
+            dirty_files,
+            local_changes_note,
+        });
+    }
 
-    // Step 4: Sometimes after pull Git asks for a merge commit
-    let _ = Command::new("git")
-        .args(&["commit", "-am", "merged"])
-        .current_dir(repo_path)
-        .output(); // Ignore failure — no merge to commit is okay
+    let remote_branch = format!("origin/{}", branch_name);
+    Self::move_head(
+        repo_path,
+        sync,
+        dirty_files,
+        policy,
+        &branch_name,
+        &[vec!["merge", "--ff-only", &remote_branch]],
+    )
+}
 
-    // Step 5: Get HEAD after pull
-    let after = Command::new("git")
-        .args(&["rev-parse", "HEAD"])
-        .current_dir(repo_path)
+/// Check out `reference` (branch, tag or commit) from origin.
+/// Branches are fast-forwarded like `pull`, tags and commits are checked out detached.
+pub fn checkout_ref(repo_path: &str, reference: &str, config: &Config) -> Result<PullResult, String> {
+    let policy = Self::local_changes_policy(repo_path, config);
+
+    let fetch = authenticated_git(repo_path, config)?
+        .args(["fetch", "--tags", "origin"])
         .output()
-        .map_err(|e| format!("Failed to get HEAD after pull: {}", e))?;
+        .map_err(|e| format!("Git fetch failed: {}", e))?;
+    if !fetch.status.success() {
+        return Err(format!("Git fetch failed: {}", String::from_utf8_lossy(&fetch.stderr)));
+    }
 
-    if !after.status.success() {
-        let err = String::from_utf8_lossy(&after.stderr);
-        return Err(format!("Failed to get HEAD after pull: {}", err));
+    let current = run_git(repo_path, &["rev-parse", "--abbrev-ref", "HEAD"])?;
+    let remote_branch = format!("origin/{}", reference);
+
+    if Self::resolve(repo_path, &remote_branch).is_ok() {
+        if current == reference {
+            return Self::pull(repo_path, config);
+        }
+
+        let dirty_files = Self::dirty_files(repo_path)?;
+
+        // Only switch if the local branch can fast-forward to origin, else the
+        // checkout would deploy its stale or local-only commits
+        let local_branch = format!("refs/heads/{}", reference);
+        if Self::resolve(repo_path, &local_branch).is_ok() {
+            let sync = Self::sync_status_of(repo_path, &local_branch, reference)?;
+            if !matches!(sync, SyncStatus::UpToDate | SyncStatus::FastForward { .. }) {
+                let local_changes_note = if dirty_files.is_empty() {
+                    None
+                } else {
+                    Some("left untouched, not switching".to_string())
+                };
+                return Ok(PullResult {
+                    sync,
+                    changed_files: Vec::new(),
+                    dirty_files,
+                    local_changes_note,
+                });
+            }
+        }
+
+        let head = run_git(repo_path, &["rev-parse", "HEAD"])?;
+        return Self::move_head(
+            repo_path,
+            SyncStatus::Switched { to: reference.to_string() },
+            dirty_files,
+            policy,
+            &head,
+            &[
+                vec!["checkout", reference],
+                vec!["merge", "--ff-only", &remote_branch],
+            ],
+        );
     }
 
-    let after_hash = String::from_utf8_lossy(&after.stdout).trim().to_string();
+    let target = Self::resolve(repo_path, reference)
+        .map_err(|_| format!("{} is not a branch, tag or commit on origin", reference))?;
+    let head = run_git(repo_path, &["rev-parse", "HEAD"])?;
+    let dirty_files = Self::dirty_files(repo_path)?;
+
+    if head == target {
+        return Ok(PullResult {
+            sync: SyncStatus::UpToDate,
+            changed_files: Vec::new(),
+            dirty_files,
+            local_changes_note: None,
+        });
+    }
 
-    // Step 6: git diff to get changed files
-    let diff = Command::new("git")
-        .args(&["diff", "--name-only", &before_hash, &after_hash])
-        .current_dir(repo_path)
+    Self::move_head(
+        repo_path,
+        SyncStatus::Switched { to: reference.to_string() },
+        dirty_files,
+        policy,
+        &head,
+        &[vec!["checkout", "--detach", &target]],
+    )
+}
+
+/// Fetch and list what updating to `reference` (or the checked out branch) would bring in,
+/// deciding like `pull` and `checkout_ref` do. The working tree is not touched.
+pub fn incoming(repo_path: &str, reference: Option<&str>, config: &Config) -> Result<Incoming, String> {
+    let fetch = authenticated_git(repo_path, config)?
+        .args(["fetch", "--tags", "origin"])
         .output()
-        .map_err(|e| format!("Git diff failed: {}", e))?;
+        .map_err(|e| format!("Git fetch failed: {}", e))?;
+    if !fetch.status.success() {
+        return Err(format!("Git fetch failed: {}", String::from_utf8_lossy(&fetch.stderr)));
+    }
+
+    let current = Self::current_branch(repo_path)?;
+    let (target, sync) = match reference {
+        Some(reference) if Self::resolve(repo_path, &format!("origin/{}", reference)).is_ok() => {
+            let sync = if current.as_deref() == Some(reference) {
+                Self::sync_status(repo_path, reference)?
+            } else {
+                // `checkout_ref` only switches to a local branch that can fast-forward
+                let local_branch = format!("refs/heads/{}", reference);
+                let local = if Self::resolve(repo_path, &local_branch).is_ok() {
+                    Self::sync_status_of(repo_path, &local_branch, reference)?
+                } else {
+                    SyncStatus::UpToDate
+                };
+                match local {
+                    SyncStatus::UpToDate | SyncStatus::FastForward { .. } => SyncStatus::Switched { to: reference.to_string() },
+                    other => other,
+                }
+            };
+            (format!("origin/{}", reference), sync)
+        }
+        Some(reference) => {
+            let commit = Self::resolve(repo_path, reference)
+                .map_err(|_| format!("{} is not a branch, tag or commit on origin", reference))?;
+            let sync = if run_git(repo_path, &["rev-parse", "HEAD"])? == commit {
+                SyncStatus::UpToDate
+            } else {
+                SyncStatus::Switched { to: reference.to_string() }
+            };
+            (reference.to_string(), sync)
+        }
+        None => match current {
+            Some(branch) => {
+                let sync = Self::sync_status(repo_path, &branch)?;
+                (format!("origin/{}", branch), sync)
+            }
+            None => return Err("HEAD is detached, pass a ref".to_string()),
+        },
+    };
+
+    // Up to date, ahead or diverged: the update leaves HEAD alone
+    if !matches!(sync, SyncStatus::FastForward { .. } | SyncStatus::Switched { .. }) {
+        return Ok(Incoming {
+            target,
+            sync,
+            commits: Vec::new(),
+            changed_files: Vec::new(),
+            aborting_changes: Vec::new(),
+        });
+    }
+
+    let aborting_changes = if Self::local_changes_policy(repo_path, config) == LocalChangesPolicy::Abort {
+        Self::dirty_files(repo_path)?
+    } else {
+        Vec::new()
+    };
+
+    let commits = run_git(repo_path, &["log", "--format=%h%x09%an%x09%s", &format!("HEAD..{}", target)])?
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, '\t');
+            Some(CommitInfo {
+                hash: parts.next()?.to_string(),
+                author: parts.next()?.to_string(),
+                subject: parts.next().unwrap_or("").to_string(),
+            })
+        })
+        .collect();
+
+    // Changes since the merge base when following the branch. A switch replaces the
+    // whole tree, e.g. going back to an older tag, so that compares the two trees.
+    let diff = match sync {
+        SyncStatus::FastForward { .. } => run_git(repo_path, &["diff", "--name-only", &format!("HEAD...{}", target)])?,
+        _ => run_git(repo_path, &["diff", "--name-only", "HEAD", &target])?,
+    };
+    let changed_files = diff
+        .lines()
+        .map(|l| l.to_string())
+        .collect();
+
+    Ok(Incoming {
+        target,
+        sync,
+        commits,
+        changed_files,
+        aborting_changes,
+    })
+}
 
-    if !diff.status.success() {
-        let err = String::from_utf8_lossy(&diff.stderr);
-        return Err(format!("Git diff failed: {}", err));
+/// Commit a ref points to
+fn resolve(repo_path: &str, reference: &str) -> Result<String, String> {
+    run_git(repo_path, &["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", reference)])
+}
+
+/// Whether HEAD is at `reference` (the origin branch for branch names), call after a fetch
+pub fn is_at_ref(repo_path: &str, reference: &str) -> Result<bool, String> {
+    let head = run_git(repo_path, &["rev-parse", "HEAD"])?;
+    let target = Self::resolve(repo_path, &format!("origin/{}", reference))
+        .or_else(|_| Self::resolve(repo_path, reference))
+        .map_err(|_| format!("Unknown ref {}", reference))?;
+    Ok(head == target)
+}
+
+fn local_changes_policy(repo_path: &str, config: &Config) -> LocalChangesPolicy {
+    let project = Path::new(repo_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    config
+        .projects
+        .get(&project)
+        .and_then(|p| p.local_changes)
+        .unwrap_or(config.local_changes)
+}
+
+/// Re-apply the stash `commit` made before an update. If it conflicts the working
+/// tree is reset to HEAD, so no conflict markers are left on the server, and the
+/// stash is kept. It is dropped only after a clean apply.
+fn reapply_stash(repo_path: &str, commit: &str) -> Result<(), String> {
+    if let Err(e) = run_git(repo_path, &["stash", "apply", commit]) {
+        let reset = run_git(repo_path, &["reset", "--hard", "HEAD"]);
+        let mut message = format!(
+            "re-applying local changes failed, they are kept in stash {} (`git stash list`): {}",
+            commit, e
+        );
+        if let Err(reset_error) = reset {
+            message.push_str(&format!("\nResetting the working tree failed too: {}", reset_error));
+        }
+        return Err(message);
+    }
+
+    // Drop it by position, but only if nothing was stashed on top in the meantime
+    if run_git(repo_path, &["rev-parse", "stash@{0}"]).is_ok_and(|top| top == commit) {
+        run_git(repo_path, &["stash", "drop", "stash@{0}"])?;
+    }
+    Ok(())
+}
+
+/// Move HEAD by running each git command in `steps`, taking care of local changes
+/// first. `return_to` is what the side-branch policy checks out again afterwards.
+fn move_head(
+    repo_path: &str,
+    sync: SyncStatus,
+    dirty_files: Vec<String>,
+    policy: LocalChangesPolicy,
+    return_to: &str,
+    steps: &[Vec<&str>],
+) -> Result<PullResult, String> {
+    // Deal with local changes, never committing on the deployed branch
+    let mut local_changes_note = None;
+    let mut stashed = None;
+
+    if !dirty_files.is_empty() {
+        match policy {
+            LocalChangesPolicy::Abort => {
+                return Err(format!(
+                    "Local changes found, not updating (policy: abort):\n  {}",
+                    dirty_files.join("\n  ")
+                ));
+            }
+            LocalChangesPolicy::Stash => {
+                let message = format!("sahakari: before update {}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"));
+                run_git(repo_path, &["stash", "push", "-m", &message])?;
+                stashed = Some(run_git(repo_path, &["rev-parse", "stash@{0}"])?);
+                local_changes_note = Some("stashed and re-applied".to_string());
+            }
+            LocalChangesPolicy::Reset => {
+                run_git(repo_path, &["reset", "--hard", "HEAD"])?;
+                local_changes_note = Some("discarded".to_string());
+            }
+            LocalChangesPolicy::SideBranch => {
+                let side_branch = format!("sahakari/local-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+                run_git(repo_path, &["checkout", "-b", &side_branch])?;
+                // Servers often have no git identity configured
+                let committed = run_git(repo_path, &[
+                    "-c", "user.name=sahakari",
+                    "-c", "user.email=sahakari@localhost",
+                    "commit", "-am", "Local changes found before update",
+                ]);
+                // Go back to the deployed branch whatever happened
+                run_git(repo_path, &["checkout", return_to])?;
+                committed?;
+                local_changes_note = Some(format!("committed on branch {}", side_branch));
+            }
+        }
+    }
+
+    // Move, fast-forward merges only so this can never create a commit
+    let before_hash = run_git(repo_path, &["rev-parse", "HEAD"])?;
+
+    for step in steps {
+        if let Err(e) = run_git(repo_path, step) {
+            if let Some(stash) = &stashed
+                && let Err(stash_error) = Self::reapply_stash(repo_path, stash)
+            {
+                return Err(format!("{}\n{}", e, stash_error));
+            }
+            return Err(e);
+        }
     }
 
-    let changed_files = String::from_utf8_lossy(&diff.stdout)
+    if let Some(stash) = &stashed {
+        Self::reapply_stash(repo_path, stash).map_err(|e| format!("Updated, but {}", e))?;
+    }
+
+    // Files that changed between the old and new HEAD
+    let after_hash = run_git(repo_path, &["rev-parse", "HEAD"])?;
+    let diff = run_git(repo_path, &["diff", "--name-only", &before_hash, &after_hash])?;
+
+    let changed_files = diff
         .lines()
         .map(|s| s.to_string())
         .collect();
 
-    Ok(changed_files)
+    Ok(PullResult {
+        sync,
+        changed_files,
+        dirty_files,
+        local_changes_note,
+    })
+}
+
+/// Find remotes of `repo_path` whose URL has credentials embedded and reset
+/// them to the bare URL. Returns `(remote, cleaned url)` for every remote
+/// that had credentials; with `dry_run` nothing is changed.
+pub fn scrub_remote_credentials(repo_path: &str, dry_run: bool) -> Result<Vec<(String, String)>, String> {
+    let output = Command::new("git")
+        .args(["config", "--get-regexp", r"^remote\..*\.(url|pushurl)$"])
+        .current_dir(repo_path)
+        .output()
+        .map_err(|e| format!("Failed to read remotes: {}", e))?;
+
+    // Exit code 1 just means there are no remotes
+    if !output.status.success() && output.status.code() != Some(1) {
+        return Err(format!("Git config error: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+
+    let mut scrubbed = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Some((key, url)) = line.split_once(' ') else {
+            continue;
+        };
+        let Some(clean_url) = strip_url_credentials(url) else {
+            continue;
+        };
+
+        if !dry_run {
+            let set = Command::new("git")
+                .args(["config", key, &clean_url])
+                .current_dir(repo_path)
+                .output()
+                .map_err(|e| format!("Failed to reset {}: {}", key, e))?;
+
+            if !set.status.success() {
+                return Err(format!(
+                    "Failed to reset {}: {}",
+                    key,
+                    String::from_utf8_lossy(&set.stderr)
+                ));
+            }
+        }
+
+        let remote = key.trim_start_matches("remote.").to_string();
+        scrubbed.push((remote, clean_url));
+    }
+
+    Ok(scrubbed)
 }
 
 }
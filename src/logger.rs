@@ -1,11 +1,17 @@
-use chrono::Local;
+use chrono::{Local, NaiveDate};
 use colored::Colorize;
+use fs2::FileExt;
 use serde::{Deserialize, Serialize};
 use std::fs::{self, OpenOptions};
 use std::io::Write;
-use std::path::Path;
+use std::os::unix::fs::FileExt as _;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+use crate::git::short_hash;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum LogLevel {
     Info,
     Warning,
@@ -18,6 +24,176 @@ pub struct LogEntry {
     pub level: LogLevel,
     pub message: String,
     pub details: Option<String>,
+    /// Set on everything logged during one `update`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    /// Who ran the tool, the sudo caller if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(flatten)]
+    pub fields: LogFields,
+}
+
+/// Structured context of an entry, all optional
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogFields {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    /// Pipeline step name, or `git` for the pull
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// HEAD before and after the git step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_before: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_after: Option<String>,
+}
+
+impl LogFields {
+    pub fn project(name: &str) -> Self {
+        LogFields {
+            project: Some(name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn step(mut self, name: &str) -> Self {
+        self.step = Some(name.to_string());
+        self
+    }
+
+    pub fn command(mut self, command: &str) -> Self {
+        self.command = Some(command.to_string());
+        self
+    }
+
+    pub fn exit_code(mut self, code: Option<i32>) -> Self {
+        self.exit_code = code;
+        self
+    }
+
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration_ms = Some(duration.as_millis() as u64);
+        self
+    }
+
+    pub fn git(mut self, before: Option<&str>, after: Option<&str>) -> Self {
+        self.git_before = before.map(|s| s.to_string());
+        self.git_after = after.map(|s| s.to_string());
+        self
+    }
+
+    fn is_empty(&self) -> bool {
+        self.project.is_none()
+            && self.step.is_none()
+            && self.command.is_none()
+            && self.exit_code.is_none()
+            && self.duration_ms.is_none()
+            && self.git_before.is_none()
+            && self.git_after.is_none()
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "info" => Ok(LogLevel::Info),
+            "warn" | "warning" => Ok(LogLevel::Warning),
+            "error" => Ok(LogLevel::Error),
+            _ => Err(format!("unknown level {}, expected info, warn or error", value)),
+        }
+    }
+}
+
+/// Which entries `logs` shows
+#[derive(Debug, Default)]
+pub struct LogQuery {
+    /// First and last day to read, today only if neither is set and no run is asked for
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+    pub project: Option<String>,
+    /// Run ID or a prefix of it
+    pub run: Option<String>,
+    /// This level and above
+    pub level: Option<LogLevel>,
+    /// Case-insensitive text in the message, details or command
+    pub grep: Option<String>,
+    /// Keep only the newest entries
+    pub limit: Option<usize>,
+    /// Newest first
+    pub reverse: bool,
+}
+
+impl LogQuery {
+    fn matches(&self, entry: &LogEntry) -> bool {
+        if let Some(level) = self.level
+            && entry.level < level
+        {
+            return false;
+        }
+        if let Some(project) = &self.project
+            && entry.fields.project.as_ref() != Some(project)
+        {
+            return false;
+        }
+        if let Some(run) = &self.run
+            && !entry.run_id.as_ref().is_some_and(|id| id.starts_with(run.as_str()))
+        {
+            return false;
+        }
+        if let Some(text) = &self.grep {
+            let text = text.to_lowercase();
+            let found = [Some(&entry.message), entry.details.as_ref(), entry.fields.command.as_ref()]
+                .into_iter()
+                .flatten()
+                .any(|field| field.to_lowercase().contains(&text));
+            if !found {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl LogEntry {
+    /// The structured fields as `name=value` pairs, `None` if there are none
+    pub fn context(&self) -> Option<String> {
+        if self.run_id.is_none() && self.fields.is_empty() {
+            return None;
+        }
+
+        let f = &self.fields;
+        let pairs = [
+            ("run", self.run_id.clone()),
+            ("project", f.project.clone()),
+            ("step", f.step.clone()),
+            ("command", f.command.clone()),
+            ("exit", f.exit_code.map(|c| c.to_string())),
+            ("took", f.duration_ms.map(|ms| format!("{:.1}s", ms as f64 / 1000.0))),
+            ("git", match (&f.git_before, &f.git_after) {
+                (Some(before), Some(after)) if before != after => Some(format!("{} → {}", short_hash(before), short_hash(after))),
+                (Some(before), _) => Some(short_hash(before).to_string()),
+                _ => None,
+            }),
+            ("user", self.user.clone()),
+        ];
+
+        Some(
+            pairs
+                .into_iter()
+                .filter_map(|(name, value)| value.map(|v| format!("{}={}", name, v)))
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    }
 }
 
 impl std::fmt::Display for LogLevel {
@@ -33,6 +209,10 @@ impl std::fmt::Display for LogLevel {
 
 pub struct Logger {
     log_path: String,
+    /// Parallel updates log from several threads, their lines must not interleave
+    write_lock: Mutex<()>,
+    run_id: OnceLock<String>,
+    user: Option<String>,
 }
 
 pub fn wrap_line(line: &str, max_width: usize) -> Vec<String> {
@@ -56,12 +236,47 @@ impl Logger {
             fs::create_dir_all(path).expect("Failed to create log directory");
         }
         
-        Logger {
+        let user = ["SUDO_USER", "USER", "LOGNAME"]
+            .iter()
+            .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+            .or_else(|| {
+                let output = std::process::Command::new("id").arg("-un").output().ok()?;
+                let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                (output.status.success() && !name.is_empty()).then_some(name)
+            });
+
+        let logger = Logger {
             log_path: log_path.to_string(),
-        }
+            write_lock: Mutex::new(()),
+            run_id: OnceLock::new(),
+            user,
+        };
+        logger.migrate_json_logs();
+        logger
+    }
+
+    /// One JSON object per line, `YYYY-MM-DD.jsonl`
+    fn log_file(&self, date: &str) -> PathBuf {
+        Path::new(&self.log_path).join(format!("{}.jsonl", date))
+    }
+
+    /// Daily file of older versions, one JSON array rewritten on every entry
+    fn legacy_log_file(&self, date: &str) -> PathBuf {
+        Path::new(&self.log_path).join(format!("{}.json", date))
     }
     
+    /// Give everything logged from now on a run ID, like `20261017-142233-3f2a`
+    pub fn start_run(&self) -> String {
+        self.run_id
+            .get_or_init(|| format!("{}-{:04x}", Local::now().format("%Y%m%d-%H%M%S"), std::process::id() & 0xffff))
+            .clone()
+    }
+
     pub fn log(&self, level: LogLevel, message: &str, details: Option<&str>) {
+        self.log_with(level, message, details, LogFields::default());
+    }
+
+    pub fn log_with(&self, level: LogLevel, message: &str, details: Option<&str>, fields: LogFields) {
         let now = Local::now();
         let timestamp = now.format("%Y-%m-%d %H:%M:%S").to_string();
         
@@ -70,39 +285,86 @@ impl Logger {
             level,
             message: message.to_string(),
             details: details.map(|s| s.to_string()),
+            run_id: self.run_id.get().cloned(),
+            user: self.user.clone(),
+            fields,
         };
         
-        // Create log file path
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+
         let date = now.format("%Y-%m-%d").to_string();
-        let log_file_path = Path::new(&self.log_path).join(format!("{}.json", date));
-        
-        // Read existing logs
-        let mut logs = if log_file_path.exists() {
-            match fs::read_to_string(&log_file_path) {
-                Ok(content) => match serde_json::from_str::<Vec<LogEntry>>(&content) {
-                    Ok(entries) => entries,
-                    Err(_) => Vec::new(),
+        if let Err(e) = self.append(&self.log_file(&date), &[log_entry]) {
+            eprintln!("{} {}", "Failed to write log:".red(), e);
+        }
+    }
+
+    /// Append entries as lines, locked against other processes and synced to disk,
+    /// so a crash loses at most the line being written
+    fn append(&self, file_path: &Path, entries: &[LogEntry]) -> Result<(), String> {
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry).map_err(|e| e.to_string())?);
+            lines.push('\n');
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(file_path)
+            .map_err(|e| e.to_string())?;
+        file.lock_exclusive().map_err(|e| e.to_string())?;
+
+        // A line cut off by a crash must not swallow the next entry
+        let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+        let mut last = [0u8];
+        if len > 0 && file.read_at(&mut last, len - 1).is_ok() && last[0] != b'\n' {
+            lines.insert(0, '\n');
+        }
+        let result = file.write_all(lines.as_bytes()).and_then(|_| file.sync_data());
+        let _ = file.unlock();
+        result.map_err(|e| e.to_string())
+    }
+
+    /// Convert the `.json` files of older versions to `.jsonl`, once: each one is
+    /// renamed to `.json.migrated` afterwards. Files that don't parse are left alone.
+    fn migrate_json_logs(&self) {
+        let Ok(dir) = fs::read_dir(&self.log_path) else {
+            return;
+        };
+
+        let mut migrated = 0;
+        for entry in dir.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let Some(date) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else {
+                continue;
+            };
+
+            let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+            let Some(entries) = read_legacy(&path) else {
+                eprintln!("{} {}", "Could not read old log file, leaving it as is:".yellow(), path.display());
+                continue;
+            };
+
+            // Appended, not prepended: rewriting the file could lose lines of another
+            // writer, `get_logs` sorts by time anyway
+            let result = self
+                .append(&self.log_file(&date), &entries)
+                .and_then(|_| fs::rename(&path, path.with_extension("json.migrated")).map_err(|e| e.to_string()));
+
+            match result {
+                Ok(()) => migrated += 1,
+                Err(e) => {
+                    eprintln!("{} {}: {}", "Failed to migrate log file".red(), path.display(), e);
                 },
-                Err(_) => Vec::new(),
             }
-        } else {
-            Vec::new()
-        };
-        
-        // Add new log entry
-        logs.push(log_entry);
-        
-        // Write logs back to file
-        if let Ok(logs_json) = serde_json::to_string_pretty(&logs) {
-            let mut file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(&log_file_path)
-                .expect("Failed to open log file");
-                
-            file.write_all(logs_json.as_bytes())
-                .expect("Failed to write to log file");
+        }
+
+        if migrated > 0 {
+            println!("{} {}", "✓ Migrated log files to JSON Lines:".green(), migrated);
         }
     }
     
@@ -117,29 +379,99 @@ impl Logger {
             &log_date_owned
         }
     };
-        
-        let log_file_path = Path::new(&self.log_path).join(format!("{}.json", log_date));
-        
-        if !log_file_path.exists() {
-            return Vec::new();
+
+        // A `.json` file is left only if its migration failed, or was copied in later
+        let mut entries = read_legacy(&self.legacy_log_file(log_date)).unwrap_or_default();
+        entries.extend(read_lines(&self.log_file(log_date)));
+        entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+
+        if let Some(filter_level) = level {
+            entries.into_iter()
+                .filter(|entry| entry.level == filter_level)
+                .collect()
+        } else {
+            entries
         }
-        
-        match fs::read_to_string(&log_file_path) {
-            Ok(content) => {
-                match serde_json::from_str::<Vec<LogEntry>>(&content) {
-                    Ok(entries) => {
-                        if let Some(filter_level) = level {
-                            entries.into_iter()
-                                .filter(|entry| entry.level == filter_level)
-                                .collect()
-                        } else {
-                            entries
-                        }
-                    },
-                    Err(_) => Vec::new(),
-                }
-            },
-            Err(_) => Vec::new(),
+    }
+
+    /// Days that have a log file, oldest first
+    fn log_dates(&self) -> Vec<NaiveDate> {
+        let mut dates = fs::read_dir(&self.log_path)
+            .map(|dir| {
+                dir.flatten()
+                    .filter_map(|entry| {
+                        let name = entry.file_name().to_string_lossy().to_string();
+                        let stem = name.strip_suffix(".jsonl").or_else(|| name.strip_suffix(".json"))?;
+                        NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        dates.sort();
+        dates.dedup();
+        dates
+    }
+
+    /// Entries of every day in the query's range that match it
+    pub fn query(&self, query: &LogQuery) -> Vec<LogEntry> {
+        let today = Local::now().date_naive();
+        let since = match (query.since, query.until, &query.run) {
+            (None, None, None) => Some(today),
+            (since, _, _) => since,
+        };
+
+        let mut entries = Vec::new();
+        for date in self.log_dates() {
+            if since.is_some_and(|since| date < since) || query.until.is_some_and(|until| date > until) {
+                continue;
+            }
+            let day = date.format("%Y-%m-%d").to_string();
+            entries.extend(self.get_logs(Some(&day), None).into_iter().filter(|e| query.matches(e)));
+        }
+
+        if let Some(limit) = query.limit {
+            entries.drain(..entries.len().saturating_sub(limit));
+        }
+        if query.reverse {
+            entries.reverse();
+        }
+        entries
+    }
+
+    /// One line per entry: time, level, run, project/step, message and outcome
+    pub fn print_compact(&self, entries: &[LogEntry]) {
+        for entry in entries {
+            let level = match entry.level {
+                LogLevel::Info => entry.level.to_string().blue(),
+                LogLevel::Warning => entry.level.to_string().yellow(),
+                LogLevel::Error => entry.level.to_string().red(),
+            };
+            let run = entry.run_id.as_deref().unwrap_or("-");
+            let place = match (&entry.fields.project, &entry.fields.step) {
+                (Some(project), Some(step)) => format!("{}/{}", project, step),
+                (Some(project), None) => project.clone(),
+                _ => String::new(),
+            };
+            let message = entry.message.lines().next().unwrap_or("");
+
+            let mut outcome = Vec::new();
+            if let Some(code) = entry.fields.exit_code {
+                outcome.push(format!("exit {}", code));
+            }
+            if let Some(ms) = entry.fields.duration_ms {
+                outcome.push(format!("{:.1}s", ms as f64 / 1000.0));
+            }
+            let outcome = if outcome.is_empty() { String::new() } else { format!(" ({})", outcome.join(", ")) };
+
+            println!(
+                "{} {} {:<20} {:<20} {}{}",
+                entry.timestamp.dimmed(),
+                level,
+                run.purple(),
+                place.cyan(),
+                message,
+                outcome.dimmed()
+            );
         }
     }
 
@@ -173,6 +505,16 @@ impl Logger {
                 }
             }
 
+            if let Some(context) = entry.context() {
+                for (i, line) in wrap_line(&context, 77).into_iter().enumerate() {
+                    if i == 0 {
+                        println!("│ Context : {:<77}│", line);
+                    } else {
+                        println!("│           {:<77}│", line);
+                    }
+                }
+            }
+
             if let Some(detail) = &entry.details {
                 for (i, line) in wrap_line(detail, 77).into_iter().enumerate() {
                     if i == 0 {
@@ -190,4 +532,22 @@ impl Logger {
 }
 
 
-}
\ No newline at end of file
+}
+
+/// Entries of a `.jsonl` file; a line cut off by a crash is skipped
+fn read_lines(path: &Path) -> Vec<LogEntry> {
+    fs::read_to_string(path)
+        .map(|content| {
+            content
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Entries of an old `.json` file, `None` if it is missing or doesn't parse
+fn read_legacy(path: &Path) -> Option<Vec<LogEntry>> {
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
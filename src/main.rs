@@ -2,29 +2,51 @@
 // Main entry point for the application
 
 
-use clap::{error, Parser, Subcommand};
+use clap::{Parser, Subcommand};
 use colored::*;
 use fs2::FileExt;
 use inquire::{Confirm, MultiSelect, Select};
-use std::{env, fs::File, io::stdout, os::unix::thread, path::{Path, PathBuf}, process::{exit, Command, Output}};
+use std::{env, fs::File, io::{stdin, stdout, IsTerminal}, path::{Path, PathBuf}, process::{exit, Command, Output}};
 use walkdir::WalkDir;
-use sysinfo::{CpuExt, CpuRefreshKind, DiskExt, RefreshKind, System, SystemExt};
+use sysinfo::{CpuExt, DiskExt, System, SystemExt};
+mod builds;
+mod changes;
 mod config;
+mod credentials;
 mod git;
+mod ssh;
 mod logger;
+mod pipeline;
+mod output;
+mod plan;
 mod project;
+mod report;
 mod state;
-use std::thread::sleep;
-use std::time::Duration;
+mod toolchain;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
 mod advance;
 use config::Config;
 use std::io::{ Write};
-use git::GitOperations;
-use logger::{Logger, LogLevel};
+use git::{short_hash, GitOperations, SyncStatus};
+use chrono::NaiveDate;
+use logger::{LogFields, LogQuery, Logger, LogLevel};
+use report::ExportFormat;
 use project::{LaravelProject, ProjectStatus};
-use state::StateManager;
+use state::{DeployRecord, Divergence, StateManager};
+
+use crate::advance::{
+    adopt_build_dir, build_inputs, build_key, cleanup_unused_parent_builds, current_build_target, ensure_js_build, is_complete_build,
+    link_build, BuildSite,
+};
+use crate::changes::ChangeSet;
+use crate::pipeline::{Builtin, FailurePolicy, OnError, Pipeline, StepError};
+
+/// Shared checkout every project's JS assets are built from
+const PARENT_REPO: &str = "/var/www/dont_delete_sahakari_main";
 
-use crate::advance::{cleanup_unused_parent_builds, ensure_js_build};
 #[derive(Parser)]
 #[clap(author, version, about)]
 struct Cli {
@@ -62,12 +84,52 @@ enum Commands {
         #[clap(long,short='f')]
         force: bool,
 
+        /// Deploy this branch, tag or commit instead of the checked out branch
+        #[clap(long = "ref", value_name = "REF")]
+        reference: Option<String>,
+
+        /// Show incoming commits and changed files and ask before updating
+        #[clap(long)]
+        preview: bool,
+
+        /// Print the dry run plan as JSON
+        #[clap(long, requires = "dry_run")]
+        json: bool,
+
+        /// What to do when a step fails: skip, abort or retry:N (default: ask, abort without a terminal)
+        #[clap(long, value_name = "POLICY")]
+        on_error: Option<OnError>,
+
+        /// Number of projects to update at once
+        #[clap(long, short = 'j', default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: u16,
+
        
         /// Update current directory,use dot(.) to update all projects
         #[clap(value_parser)]
         path: Option<String>,
     },
 
+    /// Show incoming commits and changed files without updating
+    Diff {
+        /// Project folder name, all projects if omitted
+        project: Option<String>,
+
+        /// Compare against this branch, tag or commit instead of the checked out branch
+        #[clap(long = "ref", value_name = "REF")]
+        reference: Option<String>,
+    },
+
+    /// Restore a project to the revision it had before its last update
+    Rollback {
+        /// Project folder name
+        project: String,
+
+        /// Also roll back the migration batches the update ran
+        #[clap(long)]
+        migrations: bool,
+    },
+
     /// Check system health like cpu, memory, disk usage
     Health{
         
@@ -75,15 +137,73 @@ enum Commands {
     
     /// Show logs of previous operations
     Logs {
-        /// Export logs to file
-        #[clap(long)]
-        export: Option<String>,
+        /// Export the matching entries to this file instead of printing them
+        #[clap(long, value_name = "PATH")]
+        export: Option<PathBuf>,
+
+        /// Export format: json, csv or html (default: from the file extension, else json)
+        #[clap(long, value_name = "FORMAT", requires = "export")]
+        format: Option<ExportFormat>,
         
         /// Show only errors
         #[clap(long,short='e')]
         errors: bool,
+
+        /// First day to show, YYYY-MM-DD (default: today)
+        #[clap(long, value_name = "DATE", value_parser = parse_date)]
+        since: Option<NaiveDate>,
+
+        /// Last day to show, YYYY-MM-DD
+        #[clap(long, value_name = "DATE", value_parser = parse_date)]
+        until: Option<NaiveDate>,
+
+        /// Only entries of this project
+        #[clap(long, short = 'p')]
+        project: Option<String>,
+
+        /// Only entries of this update run, a prefix of the ID is enough
+        #[clap(long, value_name = "ID")]
+        run: Option<String>,
+
+        /// Only this level and above: info, warn or error
+        #[clap(long, value_name = "LEVEL")]
+        level: Option<LogLevel>,
+
+        /// Only entries whose message, details or command contain this text
+        #[clap(long, value_name = "TEXT")]
+        grep: Option<String>,
+
+        /// Show only the newest N entries
+        #[clap(long, short = 'n', value_name = "N")]
+        limit: Option<usize>,
+
+        /// Newest first
+        #[clap(long, short = 'r')]
+        reverse: bool,
+
+        /// One line per entry instead of boxes
+        #[clap(long)]
+        oneline: bool,
     },
     
+    /// Git maintenance tasks
+    Git {
+        #[clap(subcommand)]
+        command: GitCommands,
+    },
+
+    /// JS build cache
+    #[clap(visible_alias = "builds")]
+    Build {
+        /// Use the build cache of this project instead of the parent's,
+        /// for projects with their own `build_mode`
+        #[clap(long, short = 'p', value_name = "PROJECT")]
+        project: Option<String>,
+
+        #[clap(subcommand)]
+        command: BuildCommands,
+    },
+
     /// Configure the tool
     Config {
         /// Set projects root path
@@ -98,6 +218,75 @@ enum Commands {
 }
 
 
+#[derive(Subcommand)]
+enum BuildCommands {
+    /// Print the build cache key of a project and the files it covers
+    Key {
+        /// Project folder name
+        project: String,
+
+        /// Compute the key for this branch, tag or commit instead of HEAD
+        #[clap(long = "ref", value_name = "REF")]
+        reference: Option<String>,
+    },
+
+    /// List cached builds with their size, age and the projects using them
+    List,
+
+    /// Delete the builds the retention policy doesn't keep
+    Prune {
+        /// Only show what would be deleted
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Check every cached build against its checksum manifest
+    Verify,
+
+    /// Pack a build into a .tar.zst bundle for another server
+    Export {
+        /// Build key, as shown by `builds list`
+        key: String,
+
+        /// Bundle to write, <key>.tar.zst by default
+        #[clap(long, short = 'o')]
+        output: Option<PathBuf>,
+    },
+
+    /// Add a build from a bundle made by `builds export`
+    Import {
+        /// Bundle file
+        file: PathBuf,
+    },
+
+    /// Delete one build
+    Rm {
+        /// Build key, as shown by `builds list`
+        key: String,
+
+        /// Delete it even if a project links to it
+        #[clap(long, short = 'f')]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum GitCommands {
+    /// Remove credentials embedded in remote URLs of all projects
+    Scrub {
+        /// Only report affected projects
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Add the ssh host keys of a git server to the known_hosts file
+    Trust {
+        /// Host name, e.g. github.com
+        host: String,
+    },
+}
+
+
 fn system_details() {
     let mut sys = System::new();
 
@@ -159,7 +348,7 @@ fn lock_folder(folder: &Path) -> Result<File, String> {
     let file = File::create(&lock_file_path).map_err(|e| e.to_string())?;
 
     // Try to acquire immediately
-    if let Ok(_) = file.try_lock_exclusive() {
+    if file.try_lock_exclusive().is_ok() {
         // Lock acquired silently
         return Ok(file);
     }
@@ -190,7 +379,7 @@ fn lock_folder(folder: &Path) -> Result<File, String> {
 #[allow(unused)]
 fn main() {
     // Initialize configuration
-    let folder = Path::new("/var/www/dont_delete_sahakari_main");
+    let folder = Path::new(PARENT_REPO);
 
     // Call the lock function
     let file = match lock_folder(folder) {
@@ -219,21 +408,63 @@ fn main() {
     let cli = Cli::parse();
     
     match cli.command {
-        Commands::Update { all, only, errors, dry_run, verbose,path ,force} => {
-            update_projects(&config, &logger, &state_manager, all, only, errors, dry_run, verbose,path,force);
+        Commands::Update { all, only, errors, dry_run, verbose,path ,force, reference, preview, json, on_error, jobs} => {
+            let on_error = on_error.or(config.on_error);
+            update_projects(&config, &logger, &state_manager, all, only, errors, dry_run, verbose,path,force, reference, preview, json, on_error, jobs as usize);
         },
-        Commands::Logs { export, errors } => {
-            show_logs(&logger, export, errors);
+        Commands::Diff { project, reference } => {
+            let projects = scan_for_projects(&config.projects_root)
+                .into_iter()
+                .filter(|p| project.as_ref().is_none_or(|name| &p.name == name))
+                .collect::<Vec<_>>();
+            if projects.is_empty() {
+                println!("{}", "No matching Laravel projects found.".red());
+            }
+            for project in &projects {
+                preview_project(&config, project, reference.as_deref());
+            }
+        },
+        Commands::Rollback { project, migrations } => {
+            rollback_project(&config, &logger, &state_manager, &project, migrations);
+        },
+        Commands::Logs { export, format, errors, since, until, project, run, level, grep, limit, reverse, oneline } => {
+            let query = LogQuery {
+                since,
+                until,
+                project,
+                run,
+                level: if errors { Some(LogLevel::Error) } else { level },
+                grep,
+                limit,
+                reverse,
+            };
+            match export {
+                Some(path) => export_logs(&logger, &query, &path, format),
+                None => show_logs(&logger, &query, oneline),
+            }
         },
         Commands::Config { root, show } => {
             configure(&config, root, show);
         },
+        Commands::Git { command: GitCommands::Scrub { dry_run } } => {
+            scrub_credentials(&config, &logger, dry_run);
+        },
+        Commands::Git { command: GitCommands::Trust { host } } => {
+            match ssh::trust_host(&config, &host) {
+                Ok(count) => println!("{} {} {} {}", "✓".green(), count, "host key(s) added to".green(), config.ssh.known_hosts),
+                Err(e) => println!("{} {}", "✗".red(), e),
+            }
+        },
+        Commands::Build { project, command } => {
+            manage_builds(&config, &logger, &state_manager, project.as_deref(), command);
+        },
         Commands::Health {  } => {
             system_details();
         },
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn update_projects(
     config: &Config,
     logger: &Logger,
@@ -244,16 +475,24 @@ fn update_projects(
     dry_run: bool,
     verbose: bool,
     path:Option<String>,
-    force:bool
+    force:bool,
+    reference: Option<String>,
+    preview: bool,
+    json: bool,
+    on_error: Option<OnError>,
+    jobs: usize,
 ) {
 
-    let parent = Path::new("/var/www/dont_delete_sahakari_main");
+    let parent = Path::new(PARENT_REPO);
+    let run_id = logger.start_run();
     logger.log(LogLevel::Info, "Starting Laravel project update process", None);
-    Command::new("clear")
-        .status() // or use `spawn()` for async
-        .expect("Failed to clear terminal");
+    if !json {
+        Command::new("clear")
+            .status() // or use `spawn()` for async
+            .expect("Failed to clear terminal");
+        println!("{} {}", "Run:".cyan(), run_id);
+    }
     let mut projects_to_process = Vec::new();
-    let mut total_count = 0;
 
     let is_current_path = path.as_deref() == Some(".");
     if   !is_current_path{
@@ -266,17 +505,16 @@ fn update_projects(
             return;
         }
 
-        let result = cleanup_unused_parent_builds(&projects, &parent);
-        if let Err(e) = result {
+        // A dry run must not delete anything
+        if !dry_run && let Err(e) = cleanup_unused_parent_builds(&projects, parent, config, state_manager) {
             println!("{}", e);
             logger.log(LogLevel::Error, &format!("Failed to cleanup parent builds: {}", e), None);
             return;
         }
 
         
-        if !errors{
+        if !errors && !json {
         println!("{} {} {}", "\nFound".green(), projects.len(), "Laravel projects".green());
-        total_count = projects.len();
         }
         
         // Filter projects if 'only' is specified
@@ -303,8 +541,6 @@ fn update_projects(
            
         
         if errors{
-            total_count = projects.len();
-
             println!("{} {} {}", "\nFound".green(), projects.len(), "Laravel projects with errors".red());
         }
                 
@@ -312,8 +548,6 @@ fn update_projects(
                 .prompt()
                 .unwrap_or_else(|_| Vec::new());
 
-            total_count = selected.len() ;
-
             projects.into_iter()
                 .filter(|p| selected.contains(&p.name))
                 .collect::<Vec<_>>()
@@ -350,282 +584,467 @@ fn update_projects(
     
     }
     
+    if dry_run {
+        let plans = projects_to_process.iter()
+            .map(|p| plan::plan_project(p, parent, config, reference.as_deref(), force))
+            .collect::<Vec<_>>();
+        if json {
+            println!("{}", serde_json::to_string_pretty(&plans).unwrap_or_default());
+        } else {
+            println!();
+            plan::print_plan_table(&plans);
+        }
+        logger.log(LogLevel::Info, &format!("Dry run planned {} project(s)", plans.len()), None);
+        return;
+    }
+
+    if preview {
+        for project in &projects_to_process {
+            preview_project(config, project, reference.as_deref());
+        }
+        let proceed = Confirm::new("Continue with the update?")
+            .with_default(false)
+            .prompt()
+            .unwrap_or(false);
+        if !proceed {
+            println!("{}", "Update cancelled.".yellow());
+            logger.log(LogLevel::Info, "Update cancelled after preview", None);
+            return;
+        }
+    }
+
     // Process each project
-    let mut current_count = 1;
-    'outer:for  project in projects_to_process {
-        println!("\n{}. {} {} {}/{}\n", current_count , "Processing project:".blue(),project.name.green(),current_count.to_string().purple(),total_count.to_string().cyan());
-        logger.log(LogLevel::Info, &format!("Processing project: {}", project.name), None);
-        current_count = current_count + 1;
-        // Check if we should resume from a failed state
-        
-        
-        // Git operations
-        if !dry_run {
+    let total_count = projects_to_process.len();
+    let pinned = projects_to_process.iter()
+        .filter_map(|p| {
+            let pin = config.projects.get(&p.name)?.pinned_ref.clone()?;
+            Some((p.name.clone(), p.path.clone(), pin))
+        })
+        .collect::<Vec<_>>();
+    let context = UpdateContext {
+        config,
+        logger,
+        state_manager,
+        parent,
+        reference: reference.as_deref(),
+        verbose,
+        force,
+        on_error,
+        total_count,
+    };
+    let reports = run_updates(&context, &projects_to_process, jobs);
+    if reports.iter().any(|r| r.aborted) {
+        return;
+    }
 
-            loop{
+    // (project, dirty files, what happened to them)
+    let dirty_summary = reports.iter()
+        .filter_map(|r| r.dirty.as_ref().map(|(files, outcome)| (&r.project, files, outcome)))
+        .collect::<Vec<_>>();
+    let diverged = reports.iter()
+        .filter(|r| r.diverged)
+        .map(|r| r.project.clone())
+        .collect::<Vec<_>>();
+    if !dirty_summary.is_empty() {
+        println!("\n{}", "Projects with local changes:".yellow());
+        for (name, files, outcome) in dirty_summary {
+            println!("  {} ({})", name.green(), outcome);
+            for file in files {
+                println!("    {}", file);
+            }
+        }
+    }
 
+    let off_pin = pinned.iter()
+        .filter(|(_, path, pin)| !GitOperations::is_at_ref(path, pin).unwrap_or(false))
+        .map(|(name, _, pin)| format!("{} (pinned: {})", name, pin))
+        .collect::<Vec<_>>();
+    if !off_pin.is_empty() {
+        println!("\n{}", "Not on their pinned ref:".yellow());
+        for line in &off_pin {
+            println!("  {}", line);
+        }
+        logger.log(LogLevel::Warning, "Projects not on their pinned ref", Some(&off_pin.join(", ")));
+    }
 
-            match GitOperations::pull(&project.path) {
-                Ok(changes) => {
-                    // if changes.is_empty() && !force {
-                    //     println!("  {} {}", "✓".green(), "No changes detected".green());
-                    //     logger.log(LogLevel::Info, &format!("No changes detected in {}", project.name), None);
-                    //     continue 'outer;
-                    // }
-                    
-                    println!("  {} {} {}", "✓".green(), "Pulled changes:".green(), changes.len());
-                    
-                    // Process changes
-                    // let has_php_changes = changes.iter().any(|f| f.ends_with(".php"));
-                    let has_migration_changes = changes.iter().any(|f| f.contains("migrations"));
-                    let has_js_changes = changes.iter().any(|f| f.ends_with(".js") || f.ends_with(".jsx") || f.ends_with(".ts") || f.ends_with(".tsx"));
+    if !diverged.is_empty() {
+        println!("\n{} {}", "Diverged from origin, not updated:".red(), diverged.join(", "));
+    }
 
-                    let has_composer_change = changes.iter().any(|f| f.contains("composer.json"));
-                    let has_package_change = changes.iter().any(|f| f.ends_with("package.json"));
-                    
-                    loop{
+    println!("\n{}", "All projects processed successfully.".green());
+    logger.log(LogLevel::Info, "All projects processed successfully", None);
+}
+
+/// Everything `update_project` needs besides the project
+#[derive(Clone, Copy)]
+struct UpdateContext<'a> {
+    config: &'a Config,
+    logger: &'a Logger,
+    state_manager: &'a StateManager,
+    parent: &'a Path,
+    reference: Option<&'a str>,
+    verbose: bool,
+    force: bool,
+    on_error: Option<OnError>,
+    total_count: usize,
+}
+
+/// What happened to one project during `update`
+struct ProjectReport {
+    project: String,
+    /// Locally modified files and what was done with them
+    dirty: Option<(Vec<String>, String)>,
+    diverged: bool,
+    /// The update of all projects was aborted while on this one
+    aborted: bool,
+}
+
+/// Update projects on up to `jobs` worker threads. Reports come back in project
+/// order and stop at the first project that aborted.
+fn run_updates(context: &UpdateContext, projects: &[LaravelProject], jobs: usize) -> Vec<ProjectReport> {
+    if jobs <= 1 || projects.len() <= 1 {
+        let mut reports = Vec::new();
+        for (current_count, project) in (1..).zip(projects) {
+            let report = update_project(context, current_count, project);
+            let aborted = report.aborted;
+            reports.push(report);
+            if aborted {
+                break;
+            }
+        }
+        return reports;
+    }
+
+    let next = AtomicUsize::new(0);
+    let aborted = AtomicBool::new(false);
+    let reports = Mutex::new(Vec::new());
+    let workers = jobs.min(projects.len());
+
+    output::start_board(workers);
+    thread::scope(|scope| {
+        for slot in 0..workers {
+            let (next, aborted, reports) = (&next, &aborted, &reports);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                if index >= projects.len() || aborted.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let project = &projects[index];
+                output::begin(slot, &project.name);
+                let report = update_project(context, index + 1, project);
+                output::end();
+
+                if report.aborted {
+                    aborted.store(true, Ordering::SeqCst);
+                }
+                reports.lock().unwrap().push((index, report));
+            });
+        }
+    });
+    output::finish_board();
+
+    let mut reports = reports.into_inner().unwrap();
+    reports.sort_by_key(|(index, _)| *index);
+    reports.into_iter().map(|(_, report)| report).collect()
+}
+
+/// Pull and run the pipeline of one project
+fn update_project(context: &UpdateContext, current_count: usize, project: &LaravelProject) -> ProjectReport {
+    let UpdateContext { config, logger, state_manager, parent, reference, verbose, force, on_error, total_count } = *context;
+    let mut report = ProjectReport {
+        project: project.name.clone(),
+        dirty: None,
+        diverged: false,
+        aborted: false,
+    };
+
+    outln!("\n{}. {} {} {}/{}\n", current_count , "Processing project:".blue(),project.name.green(),current_count.to_string().purple(),total_count.to_string().cyan());
+    logger.log_with(LogLevel::Info, &format!("Processing project: {}", project.name), None, LogFields::project(&project.name));
+    let dirty_before = GitOperations::dirty_files(&project.path).unwrap_or_default();
+
+    // A build made in place becomes a cached one, so `rollback` can get back to it too
+    let project_path = Path::new(&project.path);
+    let site = BuildSite::for_project(project_path, parent, config);
+    if let Err(e) = adopt_build_dir(project_path, &site.builds_dir) {
+        outln!("  {} {} {}", "!".yellow(), "Could not move public/build into the build cache:".yellow(), e);
+    }
+
+    // Remember where we came from so `rollback` can get back there
+    let mut deploy = GitOperations::head(&project.path).ok().map(|head| DeployRecord {
+        project_name: project.name.clone(),
+        timestamp: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        previous_head: head,
+        previous_branch: GitOperations::current_branch(&project.path).ok().flatten(),
+        new_head: None,
+        previous_build: current_build_target(Path::new(&project.path))
+            .map(|p| p.to_string_lossy().to_string()),
+        migration_batch_before: None,
+        migration_batch_after: None,
+    });
+
+    // Git operations
+    output::status(&project.name, "git");
+    let head_before = GitOperations::head(&project.path).ok();
+    let git_fields = || LogFields::project(&project.name).step("git");
+    let mut attempt = 0;
+    let changes = loop {
+        let started = Instant::now();
+        let target_ref = reference.map(|r| r.to_string())
+            .or_else(|| config.projects.get(&project.name).and_then(|p| p.pinned_ref.clone()));
+        let result = match &target_ref {
+            Some(target) => GitOperations::checkout_ref(&project.path, target, config),
+            None => GitOperations::pull(&project.path, config),
+        };
 
-                    // Run appropriate commands based on changes
-                    if has_composer_change {
-                        println!("  {} {}", "→".blue(), "Composer.json file changed, running composer install");
-                        if verbose {
-                            println!("    {}", "Executing: composer install".cyan());
+        match result {
+            Ok(pull_result) => {
+                if !pull_result.dirty_files.is_empty() {
+                    let note = pull_result.local_changes_note.clone().unwrap_or_default();
+                    outln!("  {} {} {} ({})", "!".yellow(), pull_result.dirty_files.len(), "locally modified file(s)".yellow(), note);
+                    logger.log_with(
+                        LogLevel::Warning,
+                        &format!("Local changes in {}: {}", project.name, note),
+                        Some(&pull_result.dirty_files.join(", ")),
+                        git_fields(),
+                    );
+                    report.dirty = Some((pull_result.dirty_files.clone(), note));
+                }
+
+                match &pull_result.sync {
+                    SyncStatus::Diverged { local_commits, remote_commits } => {
+                        outln!("  {} {}", "✗".red(), format!("Branch has diverged from origin, not updating ({} local, {} remote commit(s))", local_commits.len(), remote_commits.len()).red());
+                        for commit in local_commits {
+                            outln!("    {} {}", "local ".yellow(), commit);
                         }
-                        
-                        if !dry_run {
-                            match run_command(&project.path, "composer", &["install"]) {
-                                Ok(_) => {
-                                    println!("    {} {}", "✓".green(), "Composer install completed".green());
-                                    logger.log(LogLevel::Info, &format!("Composer install completed for {}", project.name), None);
-                                },
-                                Err(e) => {
-                                    println!("    {} {}: {}", "✗".red(), "Composer install failed".red(), e);
-                                    logger.log(LogLevel::Error, &format!("Composer install failed for {}: {}", project.name, e), None);
-                                    
-                                    // Save state for resuming later
-                                    state_manager.save_state(&project.name, "composer_install_failed");
-                                    
-                                    // Ask user what to do
-                                    let action = Select::new("What would you like to do?", vec!["Skip this project", "Retry command", "Abort all"])
-                                        .prompt()
-                                        .unwrap_or("Abort all");
-                                        
-                                    match action {
-                                        "Skip this project" => continue 'outer,
-                                        "Retry command" => {
-                                            // TODO: Implement retry logic
-                                            println!("  {} {}", "→".blue(), "Retrying composer install");
-                                            continue;
-                                        },
-                                        _ => {
-                                            println!("{}", "Aborting all operations.".red());
-                                            logger.log(LogLevel::Error, "User aborted all operations", None);
-                                            return;
-                                        }
-                                    }
-                                }
-                            }
+                        for commit in remote_commits {
+                            outln!("    {} {}", "remote".cyan(), commit);
                         }
+                        logger.log_with(
+                            LogLevel::Error,
+                            &format!("Branch of {} has diverged from origin", project.name),
+                            Some(&format!("local: {}\nremote: {}", local_commits.join(", "), remote_commits.join(", "))),
+                            git_fields().duration(started.elapsed()).git(head_before.as_deref(), head_before.as_deref()),
+                        );
+                        state_manager.save_divergence(&project.name, Divergence {
+                            local_commits: local_commits.clone(),
+                            remote_commits: remote_commits.clone(),
+                        });
+                        report.diverged = true;
+                        return report;
+                    },
+                    SyncStatus::Switched { .. } => {
+                        outln!("  {} {}", "→".blue(), pull_result.sync);
+                        logger.log_with(LogLevel::Info, &format!("{} {}", project.name, pull_result.sync), None, git_fields());
+                    },
+                    SyncStatus::Ahead { .. } => {
+                        outln!("  {} {}", "!".yellow(), format!("Local branch is {}", pull_result.sync).yellow());
+                        logger.log_with(LogLevel::Warning, &format!("{} is {}", project.name, pull_result.sync), None, git_fields());
+                    },
+                    _ => {}
+                }
+
+                let head_after = GitOperations::head(&project.path).ok();
+                logger.log_with(
+                    LogLevel::Info,
+                    &format!("Pulled {} changed file(s) for {}", pull_result.changed_files.len(), project.name),
+                    None,
+                    git_fields().duration(started.elapsed()).git(head_before.as_deref(), head_after.as_deref()),
+                );
+
+                if let Some(record) = deploy.as_mut()
+                    && let Some(new_head) = head_after
+                    && new_head != record.previous_head
+                {
+                    record.new_head = Some(new_head);
+                    state_manager.save_deploy(record);
+                }
+
+                break pull_result.changed_files;
+            },
+            Err(e) => {
+                outln!("  {} {}: {}", "✗".red(), "Git pull failed".red(), e);
+                logger.log_with(
+                    LogLevel::Error,
+                    &format!("Git pull failed for {}: {}", project.name, e),
+                    None,
+                    git_fields().duration(started.elapsed()).git(head_before.as_deref(), None),
+                );
+                
+                if !dirty_before.is_empty() && report.dirty.is_none() {
+                    report.dirty = Some((dirty_before.clone(), "not updated".to_string()));
+                }
+
+                // Save state for resuming later
+                state_manager.save_state(&project.name, "git_pull_failed");
+                
+                match failure_action(on_error, attempt) {
+                    "Skip this project" => return report,
+                    "Retry command" => {
+                        attempt += 1;
+                        outln!("  {} Retrying git pull", "→".blue());
+                        continue;
+                    },
+                    _ => {
+                        outln!("{}", "Aborting all operations.".red());
+                        logger.log(LogLevel::Error, "User aborted all operations", None);
+                        report.aborted = true;
+                        return report;
                     }
-                    
-                    if has_js_changes || force || true {
-                        // println!("  {} {}", "→".blue(), "JS files changed, running pnpm install && pnpm run dev");
-                        // if verbose {
-                        //     println!("    {}", "Executing: pnpm install".cyan());
-                        // }
-                        
-                        if !dry_run {
-
-                            if (has_package_change ||force) && false {
-                            match run_command(&project.path, "bash", &["-c"," yes | pnpm install"]) {
-                                Ok(_) => {
-                                    println!("    {} {}", "✓".green(), "pnpm install completed".green());
-                                    logger.log(LogLevel::Info, &format!("pnpm install completed for {}", project.name), None);
-                                    
-                                    if verbose {
-                                        println!("    {}", "Executing: pnpm run dev".cyan());
-                                    }
-                                    
-                                 
-                                },
-                                Err(e) => {
-                                    println!("    {} {}: {}", "✗".red(), "pnpm install failed".red(), e);
-                                    logger.log(LogLevel::Error, &format!("pnpm install failed for {}: {}", project.name, e), None);
-                                    
-                                    // Save state for resuming later
-                                    state_manager.save_state(&project.name, "npm_install_failed");
-                                    
-                                    // Ask user what to do
-                                    let action = Select::new("What would you like to do?", vec!["Skip this project", "Retry command", "Abort all"])
-                                        .prompt()
-                                        .unwrap_or("Abort all");
-                                        
-                                    match action {
-                                        "Skip this project" => continue 'outer,
-                                        "Retry command" => {
-                                            // TODO: Implement retry logic
-                                            println!("  {} {}", "→".blue(), "Retrying pnpm install");
-                                            continue;
-                                        },
-                                        _ => {
-                                            println!("{}", "Aborting all operations.".red());
-                                            logger.log(LogLevel::Error, "User aborted all operations", None);
-                                            return;
-                                        }
-                                    }
-                                }
-                            }//running command
-
-                           
-
-                            }//has package change
-
-                                   match ensure_js_build(Path::new(&project.path), &parent) {
-                                        Ok(_) => {
-                                            println!("    {} {}", "✓".green(), "pnpm run dev completed".green());
-                                            logger.log(LogLevel::Info, &format!("pnpm run dev completed for {}", project.name), None);
-                                        },
-                                        Err(e) => {
-                                            println!("    {} {}: {}", "✗".red(), "pnpm run dev failed".red(), e);
-                                            logger.log(LogLevel::Error, &format!("pnpm run dev failed for {}: {}", project.name, e), None);
-                                            
-                                            // Save state for resuming later
-                                            state_manager.save_state(&project.name, "npm_dev_failed");
-                                            
-                                            // Ask user what to do
-                                            let action = Select::new("What would you like to do?", vec!["Skip this project", "Retry command", "Abort all"])
-                                                .prompt()
-                                                .unwrap_or("Abort all");
-                                                
-                                            match action {
-                                                "Skip this project" => continue 'outer,
-                                                "Retry command" => {
-                                                    // TODO: Implement retry logic
-                                                    println!("  {} {}", "→".blue(), "Retrying npm run dev");
-                                                    continue;
-                                                },
-                                                _ => {
-                                                    println!("{}", "Aborting all operations.".red());
-                                                    logger.log(LogLevel::Error, "User aborted all operations", None);
-                                                    return;
-                                                }
-                                            }
-                                        }
-                                    }
-
-                        }//dry run
-                    }
-                    
-                    // Ask if user wants to run migrations
-                    if has_migration_changes || force
+                }
+            }
+        }
+    };
+
+    outln!("  {} {} {}", "✓".green(), "Pulled changes:".green(), changes.len());
+
+    let pipeline = match Pipeline::for_project(Path::new(&project.path)) {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            outln!("  {} {}", "✗".red(), e);
+            logger.log_with(LogLevel::Error, &format!("Invalid pipeline for {}: {}", project.name, e), None, LogFields::project(&project.name));
+            state_manager.save_state(&project.name, "pipeline_invalid");
+            return report;
+        }
+    };
+
+    let mut step_failed = false;
+    for step in pipeline.triggered(&changes, force) {
+        output::status(&project.name, &step.name);
+        outln!("  {} {}", "→".blue(), step.name);
+        if verbose {
+            outln!("    {} {}", "Executing:".cyan(), step.command().cyan());
+            for file in step.matching_files(&changes) {
+                outln!("    {} {}", "changed:".cyan(), file);
+            }
+        }
+
+        let step_fields = || LogFields::project(&project.name).step(&step.name).command(&step.command());
+        let mut attempt = 0;
+        loop {
+            let started = Instant::now();
+            let result = match step.builtin {
+                Some(Builtin::JsBuild) => ensure_js_build(Path::new(&project.path), parent, config).map_err(StepError::from),
+                Some(Builtin::Migrate) => {
+                    if let Some(record) = deploy.as_mut()
+                        && record.migration_batch_before.is_none()
                     {
-                        println!("  {} {}", "→".blue(), "Running database migrations");
-                        if verbose {
-                            println!("    {}", "Executing: php artisan migrate".cyan());
-                        }
-                        
-                        if !dry_run {
-                            match run_command(&project.path, "php", &["artisan", "migrate","--force"]) {
-                                Ok(_) => {
-                                    println!("    {} {}", "✓".green(), "Migrations completed".green());
-                                    logger.log(LogLevel::Info, &format!("Migrations completed for {}", project.name), None);
-                                },
-                                Err(e) => {
-                                    println!("    {} {}: {}", "✗".red(), "Migrations failed".red(), e);
-                                    logger.log(LogLevel::Error, &format!("Migrations failed for {}: {}", project.name, e), None);
-
-                                    let action = Select::new("What would you like to do?", vec!["Skip this project", "Retry command", "Abort all"])
-                                                .prompt()
-                                                .unwrap_or("Abort all");
-                                                
-                                            match action {
-                                                "Skip this project" => continue 'outer,
-                                                "Retry command" => {
-                                                    // TODO: Implement retry logic
-                                                    println!("  {} {}", "→".blue(), "Retrying php artisan migrate");
-                                                    continue;
-                                                },
-                                                _ => {
-                                                    println!("{}", "Aborting all operations.".red());
-                                                    logger.log(LogLevel::Error, "User aborted all operations", None);
-                                                    return;
-                                                }
-                                            }
-                                }
-                            }
+                        record.migration_batch_before = migration_batch(&project.path);
+                    }
+
+                    let migrate_result = run_command(&project.path, "php", &["artisan", "migrate","--force"]);
+
+                    if let Some(record) = deploy.as_mut() {
+                        record.migration_batch_after = migration_batch(&project.path);
+                        // `--force` or `when = "always"` migrate without an update, that's no deploy
+                        if record.changed_anything() {
+                            state_manager.save_deploy(record);
                         }
                     }
-                    
-                    // Ask if user wants to optimize
-                    // if Confirm::new("Run artisan optimize?")
-                    //     .with_default(true)
-                    //     .prompt()
-                    //     .unwrap_or(true) 
-                    // {
-                    //     println!("  {} {}", "→".blue(), "Running artisan optimize");
-                    //     if verbose {
-                    //         println!("    {}", "Executing: php artisan optimize".cyan());
-                    //     }
-                        
-                    //     if !dry_run {
-                    //         match run_command(&project.path, "php", &["artisan", "optimize"]) {
-                    //             Ok(_) => {
-                    //                 println!("    {} {}", "✓".green(), "Optimization completed".green());
-                    //                 logger.log(LogLevel::Info, &format!("Optimization completed for {}", project.name), None);
-                    //             },
-                    //             Err(e) => {
-                    //                 println!("    {} {}: {}", "✗".red(), "Optimization failed".red(), e);
-                    //                 logger.log(LogLevel::Error, &format!("Optimization failed for {}: {}", project.name, e), None);
-                    //             }
-                    //         }
-                    //     }
-                    // }
+                    migrate_result.map(|_| ())
+                },
+                None => step.run_shell(&project.path),
+            };
+
+            match result {
+                Ok(_) => {
+                    outln!("    {} {} {}", "✓".green(), step.name.green(), "completed".green());
+                    logger.log_with(
+                        LogLevel::Info,
+                        &format!("{} completed for {}", step.name, project.name),
+                        None,
+                        step_fields()
+                            .exit_code((step.builtin != Some(Builtin::JsBuild)).then_some(0))
+                            .duration(started.elapsed()),
+                    );
                     break;
-                    }//loop
-               
                 },
                 Err(e) => {
-                    println!("  {} {}: {}", "✗".red(), "Git pull failed".red(), e);
-                    logger.log(LogLevel::Error, &format!("Git pull failed for {}: {}", project.name, e), None);
-                    
+                    outln!("    {} {} {}: {}", "✗".red(), step.name.red(), "failed".red(), e);
+                    logger.log_with(
+                        LogLevel::Error,
+                        &format!("{} failed for {}: {}", step.name, project.name, e),
+                        None,
+                        step_fields().exit_code(e.exit_code).duration(started.elapsed()),
+                    );
+
                     // Save state for resuming later
-                    state_manager.save_state(&project.name, "git_pull_failed");
-                    
-                    // Ask user what to do
-                    let action = Select::new("What would you like to do?", vec!["Skip this project", "Retry command", "Abort all"])
-                        .prompt()
-                        .unwrap_or("Abort all");
-                        
+                    state_manager.save_state(&project.name, &format!("{}_failed", step.name));
+
+                    let action = match step.on_failure {
+                        FailurePolicy::Prompt => failure_action(on_error, attempt),
+                        FailurePolicy::SkipProject => "Skip this project",
+                        FailurePolicy::Abort => "Abort all",
+                        FailurePolicy::Continue => {
+                            step_failed = true;
+                            break;
+                        },
+                    };
+
                     match action {
-                        "Skip this project" => continue 'outer,
+                        "Skip this project" => return report,
                         "Retry command" => {
-                            // TODO: Implement retry logic
-                            println!("  {} {}", "→".blue(), "Retrying git pull");
+                            attempt += 1;
+                            outln!("  {} Retrying {}", "→".blue(), step.name);
                             continue;
                         },
                         _ => {
-                            println!("{}", "Aborting all operations.".red());
-                            logger.log(LogLevel::Error, "User aborted all operations", None);
-                            return;
+                            outln!("{}", "Aborting all operations.".red());
+                            logger.log(LogLevel::Error, "Aborted all operations", None);
+                            report.aborted = true;
+                            return report;
                         }
                     }
                 }
             }
-           break;
-        
-            }//git outer
-        } else {
-            println!("  {} {}", "→".yellow(), "Dry run: would pull git changes");
         }
+    }
 
-
-        
-        // Clear state after successful completion
+    // Clear state after successful completion
+    if !step_failed {
         state_manager.clear_state(&project.name);
     }
-    
-    println!("\n{}", "All projects processed successfully.".green());
-    logger.log(LogLevel::Info, "All projects processed successfully", None);
+
+    report
+}
+
+fn scrub_credentials(config: &Config, logger: &Logger, dry_run: bool) {
+    let mut repos = scan_for_projects(&config.projects_root)
+        .into_iter()
+        .map(|p| (p.name, p.path))
+        .collect::<Vec<_>>();
+    if Path::new(PARENT_REPO).join(".git").is_dir() {
+        repos.push(("(parent)".to_string(), PARENT_REPO.to_string()));
+    }
+
+    let mut affected = 0;
+    for (name, path) in repos {
+        match GitOperations::scrub_remote_credentials(&path, dry_run) {
+            Ok(scrubbed) => {
+                for (remote, clean_url) in scrubbed {
+                    affected += 1;
+                    if dry_run {
+                        println!("  {} {} {} → {}", "!".yellow(), name.green(), remote, clean_url);
+                    } else {
+                        println!("  {} {} {} → {}", "✓".green(), name.green(), remote, clean_url);
+                        logger.log(LogLevel::Warning, &format!("Removed credentials from {} of {}", remote, name), None);
+                    }
+                }
+            },
+            Err(e) => {
+                println!("  {} {}: {}", "✗".red(), name, e);
+                logger.log(LogLevel::Error, &format!("Failed to scrub remotes of {}: {}", name, e), None);
+            }
+        }
+    }
+
+    if affected == 0 {
+        println!("{}", "No remote URLs with embedded credentials found.".green());
+    } else if dry_run {
+        println!("\n{} {}", affected, "remote URL(s) contain credentials, run without --dry-run to clean them.".yellow());
+    } else {
+        println!("\n{} {}", affected, "remote URL(s) cleaned.".green());
+    }
 }
 
 fn scan_for_projects(root_path: &str) -> Vec<LaravelProject> {
@@ -663,7 +1082,7 @@ fn scan_for_projects(root_path: &str) -> Vec<LaravelProject> {
     projects
 }
 
-fn run_command(working_dir: &str, command: &str, args: &[&str]) -> Result<Output, String> {
+fn run_command(working_dir: &str, command: &str, args: &[&str]) -> Result<Output, StepError> {
      let output = Command::new(command)
         .args(args)
         .current_dir(working_dir)
@@ -672,20 +1091,412 @@ fn run_command(working_dir: &str, command: &str, args: &[&str]) -> Result<Output
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("Command failed:\n{}", stderr));
+        return Err(StepError {
+            message: format!("Command failed:\n{}", stderr),
+            exit_code: output.status.code(),
+        });
     }
 
     Ok(output)
 
 }
 
-fn show_logs(logger: &Logger, export: Option<String>, errors_only: bool) {
-    // TODO: Implement log viewing functionality
-    println!("Showing logs...");
+/// Print the commits and files an update of `project` would bring in
+fn preview_project(config: &Config, project: &LaravelProject, reference: Option<&str>) {
+    let reference = reference
+        .map(|r| r.to_string())
+        .or_else(|| config.projects.get(&project.name).and_then(|p| p.pinned_ref.clone()));
 
-    let entries = logger.get_logs(None,if errors_only { Option::Some((LogLevel::Error)) } else {None});
-    logger.print_logs(entries);
-    
+    println!("\n{}", project.name.green().bold());
+
+    let incoming = match GitOperations::incoming(&project.path, reference.as_deref(), config) {
+        Ok(incoming) => incoming,
+        Err(e) => {
+            println!("  {} {}", "✗".red(), e);
+            return;
+        }
+    };
+
+    match &incoming.sync {
+        SyncStatus::UpToDate => {
+            println!("  {} Up to date with {}", "✓".green(), incoming.target);
+            return;
+        },
+        SyncStatus::Ahead { .. } => {
+            println!("  {} {}", "!".yellow(), format!("Local branch is {}, nothing to pull", incoming.sync).yellow());
+            return;
+        },
+        SyncStatus::Diverged { local_commits, remote_commits } => {
+            println!("  {} {}", "✗".red(), format!("Branch has diverged from {}, update would skip it", incoming.target).red());
+            for commit in local_commits {
+                println!("    {} {}", "local ".yellow(), commit);
+            }
+            for commit in remote_commits {
+                println!("    {} {}", "remote".cyan(), commit);
+            }
+            return;
+        },
+        SyncStatus::FastForward { .. } | SyncStatus::Switched { .. } => {},
+    }
+
+    if !incoming.aborting_changes.is_empty() {
+        println!("  {} {}", "✗".red(), "Local changes found, update would abort (policy: abort)".red());
+        for file in &incoming.aborting_changes {
+            println!("    {}", file);
+        }
+    }
+
+    println!("  {} {} {}", incoming.commits.len(), "incoming commit(s) from".blue(), incoming.target);
+    for commit in &incoming.commits {
+        println!("    {} {} {}", commit.hash.yellow(), commit.subject, format!("({})", commit.author).purple());
+    }
+
+    let path = Path::new(&project.path);
+    let pipeline = Pipeline::for_project_at(path, &incoming.target).unwrap_or_else(|e| {
+        println!("  {} {}, grouping by the default steps", "!".yellow(), e);
+        Pipeline::default_steps()
+    });
+    let changes = ChangeSet::classify(&incoming.changed_files, &pipeline, &build_inputs(path, config));
+    for (group, files) in changes.groups() {
+        println!("  {} ({})", group.cyan(), files.len());
+        for file in files {
+            println!("    {}", file);
+        }
+    }
+
+    match (build_key(path, "HEAD", config), build_key(path, &incoming.target, config)) {
+        (Ok(current), Ok(next)) if current.key != next.key => {
+            println!("  {} JS build hash changes: {} → {}", "!".yellow(), short_hash(&current.key), short_hash(&next.key));
+        },
+        (Ok(_), Ok(_)) => println!("  {} JS build hash unchanged", "✓".green()),
+        _ => println!("  {} Could not determine JS build hash", "!".yellow()),
+    }
+}
+
+/// `builds list | prune | rm | key`
+fn manage_builds(
+    config: &Config,
+    logger: &Logger,
+    state_manager: &StateManager,
+    project: Option<&str>,
+    command: BuildCommands,
+) {
+    let parent = Path::new(PARENT_REPO);
+    let projects = scan_for_projects(&config.projects_root);
+    let policy = &config.build_retention;
+
+    let site = match project {
+        Some(name) => match projects.iter().find(|p| p.name == name) {
+            Some(project) => BuildSite::for_project(Path::new(&project.path), parent, config),
+            None => {
+                println!("{} {}", "Project not found:".red(), name);
+                return;
+            }
+        },
+        None => BuildSite::shared(parent),
+    };
+    let builds_dir = site.builds_dir.as_path();
+
+    let builds = match builds::list_builds(builds_dir, &projects, state_manager) {
+        Ok(builds) => builds,
+        Err(e) => {
+            println!("{} {}", "✗".red(), e);
+            return;
+        }
+    };
+
+    match command {
+        BuildCommands::Key { project, reference } => show_build_key(config, &project, reference.as_deref()),
+        BuildCommands::List => builds::print_builds(&builds, policy),
+        BuildCommands::Verify => {
+            let mut broken = 0;
+            for build in &builds {
+                match builds::verify_build(&builds_dir.join(&build.key)) {
+                    Ok(()) => println!("  {} {}", "✓".green(), build.key),
+                    Err(e) => {
+                        broken += 1;
+                        println!("  {} {}: {}", "✗".red(), build.key, e.red());
+                        logger.log(LogLevel::Warning, &format!("Build {} failed verification: {}", build.key, e), None);
+                    },
+                }
+            }
+            if broken == 0 {
+                println!("\n{} {}", builds.len(), "build(s) verified".green());
+            } else {
+                println!("\n{} {} {}", broken, "of".red(), format!("{} build(s) failed verification, they get rebuilt when next needed", builds.len()).red());
+            }
+        },
+        BuildCommands::Prune { dry_run } => {
+            println!(
+                "{} keep last {}, younger than {} days{}",
+                "Retention:".cyan(),
+                policy.keep_last,
+                policy.keep_days,
+                if policy.keep_deployed { ", rollback targets" } else { "" }
+            );
+            let freed = builds::prune(builds_dir, &builds, policy, dry_run);
+            if dry_run {
+                println!("{} {}", "Would free".yellow(), builds::human_size(freed));
+            } else {
+                println!("{} {}", "✓ Freed".green(), builds::human_size(freed));
+                logger.log(LogLevel::Info, &format!("Pruned builds, freed {}", builds::human_size(freed)), None);
+            }
+        },
+        BuildCommands::Export { key, output } => {
+            let build = match builds::find_build(&builds, &key) {
+                Ok(build) => build,
+                Err(e) => {
+                    println!("{}", e.red());
+                    return;
+                }
+            };
+
+            let output = output.unwrap_or_else(|| PathBuf::from(format!("{}.tar.zst", build.key)));
+            match builds::export_build(builds_dir, &build.key, &output) {
+                Ok(size) => {
+                    println!("{} {} {} {} {}", "✓".green(), "Exported".green(), build.key, "to".green(), output.display());
+                    println!("  {} → {}", builds::human_size(build.size), builds::human_size(size));
+                    logger.log(LogLevel::Info, &format!("Exported build {} to {}", build.key, output.display()), None);
+                },
+                Err(e) => println!("{} {}", "✗".red(), e),
+            }
+        },
+        BuildCommands::Import { file } => {
+            match builds::import_build(builds_dir, &file) {
+                Ok(key) => {
+                    println!("{} {} {}", "✓".green(), "Imported and verified build".green(), key);
+                    logger.log(LogLevel::Info, &format!("Imported build {} from {}", key, file.display()), None);
+                },
+                Err(e) => {
+                    println!("{} {}", "✗".red(), e);
+                    logger.log(LogLevel::Error, &format!("Import of {} failed: {}", file.display(), e), None);
+                },
+            }
+        },
+        BuildCommands::Rm { key, force } => {
+            let build = match builds::find_build(&builds, &key) {
+                Ok(build) => build,
+                Err(e) => {
+                    println!("{}", e.red());
+                    return;
+                }
+            };
+
+            if !build.linked_by.is_empty() && !force {
+                println!("{} {} {}", "✗".red(), "Build is linked by".red(), build.linked_by.join(", "));
+                println!("  Use --force to delete it anyway");
+                return;
+            }
+
+            match builds::remove_build(builds_dir, &build.key) {
+                Ok(()) => {
+                    println!("{} {} {} ({})", "✓".green(), "Deleted build".green(), build.key, builds::human_size(build.size));
+                    logger.log(LogLevel::Info, &format!("Deleted build {}", build.key), None);
+                },
+                Err(e) => println!("{} {}", "✗".red(), e),
+            }
+        },
+    }
+}
+
+/// `build key`: the key `update` would build or link for a project
+fn show_build_key(config: &Config, name: &str, reference: Option<&str>) {
+    let Some(project) = scan_for_projects(&config.projects_root).into_iter().find(|p| p.name == name) else {
+        println!("{} {}", "Project not found:".red(), name);
+        return;
+    };
+
+    let build = match build_key(Path::new(&project.path), reference.unwrap_or("HEAD"), config) {
+        Ok(build) => build,
+        Err(e) => {
+            println!("{} {}", "✗".red(), e);
+            return;
+        }
+    };
+
+    let site = BuildSite::for_project(Path::new(&project.path), Path::new(PARENT_REPO), config);
+    let cached = is_complete_build(&site.builds_dir.join(&build.key));
+    println!("{} {}", "Key:   ".cyan(), build.key);
+    println!("{} {}", "Commit:".cyan(), build.commit);
+    println!("{} {} ({})", "Cached:".cyan(), if cached { "yes".green() } else { "no".yellow() }, site.builds_dir.display());
+    println!("\n{} ({})", "Toolchain".cyan(), build.toolchain.package_manager);
+    for line in build.toolchain.describe().lines() {
+        println!("  {}", line);
+    }
+    println!("\n{} ({})", "Inputs".cyan(), build.inputs.len());
+    for (path, id) in &build.inputs {
+        println!("  {}  {}", short_hash(id).dimmed(), path);
+    }
+}
+
+/// What to do after the `attempt`th failure of a step: follow the `--on-error`
+/// policy, ask when running in a terminal, abort otherwise
+fn failure_action(on_error: Option<OnError>, attempt: u32) -> &'static str {
+    match on_error {
+        Some(OnError::Skip) => "Skip this project",
+        Some(OnError::Abort) => "Abort all",
+        Some(OnError::Retry(max)) if attempt < max => {
+            // 2s, 4s, 8s, ... capped at a minute
+            let delay = Duration::from_secs((2u64 << attempt.min(5)).min(60));
+            outln!("  {} Attempt {}/{}, waiting {}s", "→".blue(), attempt + 1, max, delay.as_secs());
+            sleep(delay);
+            "Retry command"
+        },
+        Some(OnError::Retry(max)) => {
+            outln!("  {} Giving up after {} retries, skipping project", "!".yellow(), max);
+            "Skip this project"
+        },
+        None if stdin().is_terminal() => output::interactive(|| {
+            Select::new("What would you like to do?", vec!["Skip this project", "Retry command", "Abort all"])
+                .prompt()
+                .unwrap_or("Abort all")
+        }),
+        None => "Abort all",
+    }
+}
+
+/// Last migration batch number, `None` if it can't be determined
+fn migration_batch(project_path: &str) -> Option<u32> {
+    let output = run_command(project_path, "php", &[
+        "artisan",
+        "tinker",
+        "--execute=echo app('migration.repository')->getLastBatchNumber();",
+    ]).ok()?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|l| l.trim().parse::<u32>().ok())
+        .next_back()
+}
+
+fn rollback_project(config: &Config, logger: &Logger, state_manager: &StateManager, name: &str, migrations: bool) {
+    let Some(project) = scan_for_projects(&config.projects_root).into_iter().find(|p| p.name == name) else {
+        println!("{} {}", "Project not found:".red(), name);
+        return;
+    };
+
+    // Older versions also recorded updates that changed nothing, drop those
+    let mut history = state_manager.deploy_history(name);
+    let Some(record) = (loop {
+        match history.pop() {
+            Some(record) if !record.changed_anything() => {
+                println!("  {} Skipping the update of {}, it changed nothing", "!".yellow(), record.timestamp);
+                state_manager.pop_deploy(name);
+            },
+            other => break other,
+        }
+    }) else {
+        println!("{} {}", "No recorded update to roll back for".yellow(), name);
+        return;
+    };
+
+    println!("{} {} to the state before the update of {}", "Rolling back".blue(), name.green(), record.timestamp);
+    logger.log(LogLevel::Info, &format!("Rolling back {} to {}", name, record.previous_head), None);
+
+    // Migrations first, the files to roll them back only exist in the new code
+    if migrations {
+        match (record.migration_batch_before, record.migration_batch_after) {
+            (Some(before), Some(after)) if after > before => {
+                // Rolling back batches someone migrated after the update would undo theirs
+                match migration_batch(&project.path) {
+                    Some(current) if current == after => {},
+                    Some(current) => {
+                        println!(
+                            "  {} {} (batch {} now, {} after the update), not rolling back",
+                            "✗".red(),
+                            "Migrations ran since the update".red(),
+                            current,
+                            after
+                        );
+                        logger.log(
+                            LogLevel::Error,
+                            &format!("Rollback of {} refused: migration batch is {}, was {} after the update", name, current, after),
+                            None,
+                        );
+                        return;
+                    },
+                    None => {
+                        println!("  {} {}, not rolling back", "✗".red(), "Could not read the current migration batch".red());
+                        logger.log(LogLevel::Error, &format!("Rollback of {} refused: current migration batch unknown", name), None);
+                        return;
+                    },
+                }
+
+                for _ in before..after {
+                    println!("  {} Rolling back migration batch", "→".blue());
+                    if let Err(e) = run_command(&project.path, "php", &["artisan", "migrate:rollback", "--force"]) {
+                        println!("    {} {}: {}", "✗".red(), "Migration rollback failed".red(), e);
+                        logger.log(LogLevel::Error, &format!("Migration rollback failed for {}: {}", name, e), None);
+                        return;
+                    }
+                }
+                println!("    {} {} {}", "✓".green(), after - before, "migration batch(es) rolled back".green());
+            },
+            (Some(_), Some(_)) => println!("  {} No migrations were applied by the update", "→".blue()),
+            _ => println!("  {} No migration batches recorded, skipping", "!".yellow()),
+        }
+    }
+
+    let short = short_hash(&record.previous_head);
+    match GitOperations::restore(&project.path, record.previous_branch.as_deref(), &record.previous_head) {
+        Ok(_) => println!("  {} {} {}", "✓".green(), "Git reset to".green(), short),
+        Err(e) => {
+            println!("  {} {}: {}", "✗".red(), "Git reset failed".red(), e);
+            logger.log(LogLevel::Error, &format!("Rollback of {} failed: {}", name, e), None);
+            return;
+        }
+    }
+
+    if let Some(previous_build) = &record.previous_build {
+        let target = Path::new(previous_build);
+        if target.is_dir() {
+            match link_build(Path::new(&project.path), target) {
+                Ok(_) => println!("  {} {} {}", "✓".green(), "Re-linked build".green(), previous_build),
+                Err(e) => println!("  {} {}: {}", "✗".red(), "Re-linking build failed".red(), e),
+            }
+        } else {
+            println!("  {} {} {}", "!".yellow(), "Previous build no longer exists:".yellow(), previous_build);
+        }
+    }
+
+    state_manager.pop_deploy(name);
+    logger.log(LogLevel::Info, &format!("Rolled back {} to {}", name, record.previous_head), None);
+    println!("{}", "Rollback completed".green());
+}
+
+fn show_logs(logger: &Logger, query: &LogQuery, oneline: bool) {
+    let entries = logger.query(query);
+    if entries.is_empty() {
+        println!("{}", "No log entries found".yellow());
+        return;
+    }
+
+    if oneline {
+        logger.print_compact(&entries);
+    } else {
+        logger.print_logs(entries);
+    }
+}
+
+/// `logs --export`: write the entries `query` matches to `path`
+fn export_logs(logger: &Logger, query: &LogQuery, path: &Path, format: Option<ExportFormat>) {
+    let entries = logger.query(query);
+    let format = format.unwrap_or_else(|| ExportFormat::from_path(path));
+
+    match report::export_logs(&entries, path, format) {
+        Ok(()) => println!(
+            "{} {} {} {}",
+            "✓".green(),
+            format!("Exported {} entries to", entries.len()).green(),
+            path.display(),
+            format!("({})", format).dimmed()
+        ),
+        Err(e) => println!("{} {}", "✗".red(), e),
+    }
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| format!("expected YYYY-MM-DD, got {}", value))
 }
 
 fn configure(config: &Config, root: Option<String>, show: bool) {
@@ -694,6 +1505,28 @@ fn configure(config: &Config, root: Option<String>, show: bool) {
         println!("  Projects root: {}", config.projects_root);
         println!("  Log path: {}", config.log_path);
         println!("  State path: {}", config.state_path);
+        let sources = config.credentials.sources.iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("  Credential sources: {}", sources);
+        for (host, host_config) in &config.credentials.hosts {
+            println!("    {}: {}", host, host_config.username.as_deref().unwrap_or("(any account)"));
+        }
+        println!("  SSH known hosts: {} (checking: {})", config.ssh.known_hosts, config.ssh.host_key_checking);
+        for (host, key) in &config.ssh.keys {
+            println!("    {}: {}", host, key);
+        }
+        for (name, project) in &config.projects {
+            if let Some(key) = &project.deploy_key {
+                println!("    {} (project): {}", name, key);
+            }
+        }
+        for (name, project) in &config.projects {
+            if let Some(pin) = &project.pinned_ref {
+                println!("  Pinned: {} → {}", name, pin);
+            }
+        }
         return;
     }
     
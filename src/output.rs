@@ -0,0 +1,154 @@
+//! Output of `update`. With a single job lines are printed straight away; with
+//! `--jobs N` each worker buffers the lines of its project and prints them in
+//! one piece when the project is done, below a live status line per worker.
+
+use std::cell::{Cell, RefCell};
+use std::io::{stdout, IsTerminal, Write};
+use std::sync::Mutex;
+
+use colored::*;
+use once_cell::sync::Lazy;
+
+/// `println!` for code that may run on an update worker
+#[macro_export]
+macro_rules! outln {
+    () => {
+        $crate::output::line(String::new())
+    };
+    ($($arg:tt)*) => {
+        $crate::output::line(format!($($arg)*))
+    };
+}
+
+thread_local! {
+    /// Lines of the project this thread is working on, `None` when printing directly
+    static BUFFER: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+    /// Row of this worker on the status board
+    static SLOT: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// One status line per worker, redrawn in place below the finished projects
+struct Board {
+    rows: Vec<String>,
+    /// Lines currently on screen
+    drawn: usize,
+    /// Only redraw on a terminal, otherwise just print finished projects
+    live: bool,
+}
+
+static BOARD: Lazy<Mutex<Board>> = Lazy::new(|| {
+    Mutex::new(Board {
+        rows: Vec::new(),
+        drawn: 0,
+        live: false,
+    })
+});
+
+impl Board {
+    fn clear(&mut self) {
+        if self.drawn > 0 {
+            print!("\x1b[{}A\x1b[J", self.drawn);
+            self.drawn = 0;
+        }
+    }
+
+    fn draw(&mut self) {
+        if !self.live {
+            return;
+        }
+        self.clear();
+        for row in &self.rows {
+            println!("{}", row);
+        }
+        self.drawn = self.rows.len();
+        stdout().flush().ok();
+    }
+}
+
+fn idle_row() -> String {
+    format!("  {} {}", "·".dimmed(), "idle".dimmed())
+}
+
+fn print_buffered(lines: Vec<String>) {
+    for line in lines {
+        println!("{}", line);
+    }
+}
+
+/// Print a line, or add it to the current project's output on a worker
+pub fn line(text: String) {
+    BUFFER.with(|buffer| match buffer.borrow_mut().as_mut() {
+        Some(lines) => lines.push(text),
+        None => println!("{}", text),
+    });
+}
+
+/// Show the status board for `workers` parallel jobs
+pub fn start_board(workers: usize) {
+    let mut board = BOARD.lock().unwrap();
+    board.rows = vec![idle_row(); workers];
+    board.live = stdout().is_terminal();
+    board.draw();
+}
+
+/// Remove the status board once all workers are done
+pub fn finish_board() {
+    let mut board = BOARD.lock().unwrap();
+    board.clear();
+    board.rows.clear();
+}
+
+/// Start buffering the output of `project` on worker `slot`
+pub fn begin(slot: usize, project: &str) {
+    SLOT.with(|s| s.set(Some(slot)));
+    BUFFER.with(|buffer| *buffer.borrow_mut() = Some(Vec::new()));
+
+    let mut board = BOARD.lock().unwrap();
+    if let Some(row) = board.rows.get_mut(slot) {
+        *row = format!("  {} {}", "⟳".cyan(), project.green());
+    }
+    board.draw();
+}
+
+/// Show what the current worker is doing, e.g. the running step
+pub fn status(project: &str, text: &str) {
+    let Some(slot) = SLOT.with(|s| s.get()) else {
+        return;
+    };
+
+    let mut board = BOARD.lock().unwrap();
+    if let Some(row) = board.rows.get_mut(slot) {
+        *row = format!("  {} {} {}", "⟳".cyan(), project.green(), text);
+    }
+    board.draw();
+}
+
+/// Print the buffered output of the current worker's project above the board
+pub fn end() {
+    let lines = BUFFER.with(|buffer| buffer.borrow_mut().take()).unwrap_or_default();
+    let slot = SLOT.with(|s| s.take());
+
+    let mut board = BOARD.lock().unwrap();
+    board.clear();
+    print_buffered(lines);
+    if let Some(row) = slot.and_then(|slot| board.rows.get_mut(slot)) {
+        *row = idle_row();
+    }
+    board.draw();
+}
+
+/// Run a prompt. On a worker, other workers' output waits and the project's
+/// output so far is printed first, so the question has its context.
+pub fn interactive<T>(prompt: impl FnOnce() -> T) -> T {
+    let buffered = BUFFER.with(|buffer| buffer.borrow_mut().as_mut().map(std::mem::take));
+    let Some(lines) = buffered else {
+        return prompt();
+    };
+
+    let mut board = BOARD.lock().unwrap();
+    board.clear();
+    print_buffered(lines);
+    let answer = prompt();
+    board.draw();
+    answer
+}
@@ -0,0 +1,381 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+
+/// Per project pipeline overrides, read from the project root
+pub const PROJECT_FILE: &str = ".sahakari.toml";
+
+/// Paths matching any of `globs`. `*` stays within a directory, `**` crosses them.
+pub fn matching_paths(globs: &[String], paths: &[String]) -> Vec<String> {
+    let patterns = globs
+        .iter()
+        .filter_map(|g| Pattern::new(g).ok())
+        .collect::<Vec<_>>();
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+
+    paths
+        .iter()
+        .filter(|f| patterns.iter().any(|p| p.matches_with(f, options)))
+        .cloned()
+        .collect()
+}
+
+/// When a step runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Trigger {
+    /// `always` or `force`
+    Keyword(TriggerKeyword),
+    /// Run when a changed file matches one of these globs, or with `--force`
+    Paths(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TriggerKeyword {
+    /// Every update
+    Always,
+    /// Only with `update --force`
+    Force,
+}
+
+/// What happens when a step fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailurePolicy {
+    /// Follow `--on-error`, or ask whether to skip, retry or abort when running in a terminal
+    #[default]
+    Prompt,
+    /// Stop processing this project and go on with the next one
+    SkipProject,
+    /// Stop the whole update
+    Abort,
+    /// Report the failure and run the remaining steps anyway
+    Continue,
+}
+
+/// Unattended handling of failed steps, `--on-error` and `on_error` in the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum OnError {
+    /// Go on with the next project
+    Skip,
+    /// Stop the whole update
+    Abort,
+    /// Retry up to N times with exponential backoff, then skip the project
+    Retry(u32),
+}
+
+impl std::fmt::Display for OnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OnError::Skip => write!(f, "skip"),
+            OnError::Abort => write!(f, "abort"),
+            OnError::Retry(n) => write!(f, "retry:{}", n),
+        }
+    }
+}
+
+impl std::str::FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(OnError::Skip),
+            "abort" => Ok(OnError::Abort),
+            _ => match s.strip_prefix("retry:").map(|n| n.parse::<u32>()) {
+                Some(Ok(n)) => Ok(OnError::Retry(n)),
+                _ => Err(format!("invalid on-error policy `{}`, expected skip, abort or retry:N", s)),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for OnError {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<OnError> for String {
+    fn from(value: OnError) -> Self {
+        value.to_string()
+    }
+}
+
+/// Steps implemented by the tool itself rather than a shell command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Builtin {
+    /// Link or build the JS assets, see `advance::ensure_js_build`
+    JsBuild,
+    /// `php artisan migrate --force`, recording migration batches for rollback
+    Migrate,
+}
+
+/// Why a step failed, with the exit code if it ran a command that exited non-zero
+#[derive(Debug, Clone)]
+pub struct StepError {
+    pub message: String,
+    pub exit_code: Option<i32>,
+}
+
+impl From<String> for StepError {
+    fn from(message: String) -> Self {
+        StepError { message, exit_code: None }
+    }
+}
+
+impl std::fmt::Display for StepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub name: String,
+    /// Shell command, run with `sh -c` in the project root
+    pub run: Option<String>,
+    pub builtin: Option<Builtin>,
+    pub when: Trigger,
+    pub on_failure: FailurePolicy,
+}
+
+/// A `[[steps]]` entry of `.sahakari.toml`. Entries named like an existing step
+/// change only the fields they set, other entries add a new step.
+#[derive(Debug, Deserialize)]
+struct StepSpec {
+    name: String,
+    #[serde(default)]
+    run: Option<String>,
+    #[serde(default)]
+    builtin: Option<Builtin>,
+    /// Defaults to `always` for new steps
+    #[serde(default)]
+    when: Option<Trigger>,
+    #[serde(default)]
+    on_failure: Option<FailurePolicy>,
+    /// Insert before/after the named step instead of appending (new steps only)
+    #[serde(default)]
+    before: Option<String>,
+    #[serde(default)]
+    after: Option<String>,
+    /// Remove the step with this name
+    #[serde(default)]
+    disabled: bool,
+}
+
+impl Step {
+    fn new(name: &str, builtin: Option<Builtin>, run: Option<&str>, when: Trigger) -> Self {
+        Step {
+            name: name.to_string(),
+            run: run.map(|r| r.to_string()),
+            builtin,
+            when,
+            on_failure: FailurePolicy::Prompt,
+        }
+    }
+
+    /// Human readable command, for output and dry runs
+    pub fn command(&self) -> String {
+        match (&self.builtin, &self.run) {
+            (Some(Builtin::JsBuild), _) => "JS build".to_string(),
+            (Some(Builtin::Migrate), _) => "php artisan migrate --force".to_string(),
+            (None, Some(run)) => run.clone(),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// Changed files that match this step's path globs
+    pub fn matching_files(&self, changed_files: &[String]) -> Vec<String> {
+        let Trigger::Paths(globs) = &self.when else {
+            return Vec::new();
+        };
+
+        matching_paths(globs, changed_files)
+    }
+
+    /// Whether the step runs for these changed files
+    pub fn is_triggered(&self, changed_files: &[String], force: bool) -> bool {
+        match &self.when {
+            Trigger::Keyword(TriggerKeyword::Always) => true,
+            Trigger::Keyword(TriggerKeyword::Force) => force,
+            Trigger::Paths(_) => force || !self.matching_files(changed_files).is_empty(),
+        }
+    }
+
+    /// Run a shell step in `project_path`
+    pub fn run_shell(&self, project_path: &str) -> Result<(), StepError> {
+        let Some(run) = &self.run else {
+            return Err(format!("Step {} has neither `run` nor `builtin`", self.name).into());
+        };
+
+        let output = Command::new("sh")
+            .args(["-c", run])
+            .current_dir(project_path)
+            .output()
+            .map_err(|e| format!("Failed to launch command: {}", e))?;
+
+        if !output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(StepError {
+                message: format!("Command failed:\n{}\n{}", stdout.trim(), stderr.trim()),
+                exit_code: output.status.code(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Contents of `.sahakari.toml`
+#[derive(Debug, Default, Deserialize)]
+struct ProjectFile {
+    /// Drop the default steps and only run the ones listed here
+    #[serde(default)]
+    replace_default: bool,
+    #[serde(default)]
+    steps: Vec<StepSpec>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    pub steps: Vec<Step>,
+}
+
+impl Pipeline {
+    /// Today's behaviour: composer install when composer files change, always
+    /// link or build the JS assets, migrate when migrations change.
+    pub fn default_steps() -> Self {
+        Pipeline {
+            steps: vec![
+                Step::new(
+                    "composer_install",
+                    None,
+                    Some("composer install"),
+                    Trigger::Paths(vec!["composer.json".to_string(), "composer.lock".to_string()]),
+                ),
+                Step::new("js_build", Some(Builtin::JsBuild), None, Trigger::Keyword(TriggerKeyword::Always)),
+                Step::new(
+                    "migrate",
+                    Some(Builtin::Migrate),
+                    None,
+                    Trigger::Paths(vec!["**/migrations/**".to_string()]),
+                ),
+            ],
+        }
+    }
+
+    /// Default pipeline merged with the project's `.sahakari.toml`, if any
+    pub fn for_project(project_path: &Path) -> Result<Self, String> {
+        let file = project_path.join(PROJECT_FILE);
+        if !file.exists() {
+            return Ok(Self::default_steps());
+        }
+
+        let content = fs::read_to_string(&file).map_err(|e| e.to_string())?;
+        Self::from_toml(&content).map_err(|e| format!("Invalid {}: {}", file.display(), e))
+    }
+
+    /// Same as `for_project`, but reads `.sahakari.toml` as of `rev` without checking it out
+    pub fn for_project_at(project_path: &Path, rev: &str) -> Result<Self, String> {
+        let output = Command::new("git")
+            .args(["show", &format!("{}:{}", rev, PROJECT_FILE)])
+            .current_dir(project_path)
+            .output()
+            .map_err(|e| e.to_string())?;
+
+        // Not committed in that revision, an untracked file in the working tree still counts
+        if !output.status.success() {
+            return Self::for_project(project_path);
+        }
+
+        Self::from_toml(&String::from_utf8_lossy(&output.stdout))
+            .map_err(|e| format!("Invalid {} in {}: {}", PROJECT_FILE, rev, e))
+    }
+
+    fn from_toml(content: &str) -> Result<Self, String> {
+        let file: ProjectFile = toml::from_str(content).map_err(|e| e.to_string())?;
+
+        let mut pipeline = if file.replace_default {
+            Pipeline { steps: Vec::new() }
+        } else {
+            Self::default_steps()
+        };
+
+        for spec in file.steps {
+            // Same name as an existing step: change or remove it in place
+            if let Some(index) = pipeline.steps.iter().position(|s| s.name == spec.name) {
+                if spec.disabled {
+                    pipeline.steps.remove(index);
+                    continue;
+                }
+
+                let step = &mut pipeline.steps[index];
+                if spec.run.is_some() || spec.builtin.is_some() {
+                    step.run = spec.run;
+                    step.builtin = spec.builtin;
+                }
+                if let Some(when) = spec.when {
+                    step.when = when;
+                }
+                if let Some(on_failure) = spec.on_failure {
+                    step.on_failure = on_failure;
+                }
+                continue;
+            }
+
+            if spec.disabled {
+                continue;
+            }
+            if spec.run.is_none() && spec.builtin.is_none() {
+                return Err(format!("step {} needs `run` or `builtin`", spec.name));
+            }
+
+            let anchor = |name: &Option<String>| -> Result<Option<usize>, String> {
+                let Some(name) = name else {
+                    return Ok(None);
+                };
+                pipeline
+                    .steps
+                    .iter()
+                    .position(|s| &s.name == name)
+                    .map(Some)
+                    .ok_or_else(|| format!("step {} is placed next to unknown step {}", spec.name, name))
+            };
+            let index = match (anchor(&spec.before)?, anchor(&spec.after)?) {
+                (Some(i), _) => i,
+                (None, Some(i)) => i + 1,
+                (None, None) => pipeline.steps.len(),
+            };
+
+            pipeline.steps.insert(index, Step {
+                name: spec.name,
+                run: spec.run,
+                builtin: spec.builtin,
+                when: spec.when.unwrap_or(Trigger::Keyword(TriggerKeyword::Always)),
+                on_failure: spec.on_failure.unwrap_or_default(),
+            });
+        }
+
+        Ok(pipeline)
+    }
+
+    /// Steps that run for these changed files, in order
+    pub fn triggered(&self, changed_files: &[String], force: bool) -> Vec<&Step> {
+        self.steps
+            .iter()
+            .filter(|s| s.is_triggered(changed_files, force))
+            .collect()
+    }
+}
@@ -0,0 +1,185 @@
+use std::path::Path;
+
+use colored::*;
+use serde::Serialize;
+
+use crate::advance::{build_inputs, build_key, is_complete_build, BuildSite};
+use crate::changes::ChangeSet;
+use crate::config::Config;
+use crate::git::{GitOperations, SyncStatus};
+use crate::pipeline::{Builtin, Pipeline};
+use crate::project::LaravelProject;
+
+/// One step `update` would run for a project
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedStep {
+    pub step: String,
+    pub command: String,
+    /// Why the step runs, e.g. the files that trigger it
+    pub details: Vec<String>,
+}
+
+/// Everything `update` would do to a project, computed without touching the working tree
+#[derive(Debug, Clone, Serialize)]
+pub struct ProjectPlan {
+    pub project: String,
+    pub target: Option<String>,
+    pub incoming_commits: usize,
+    pub changed_files: Vec<String>,
+    pub steps: Vec<PlannedStep>,
+    /// Why the update would leave the project alone, e.g. it diverged from origin
+    pub skipped: Option<String>,
+    /// Locally modified files that make the update abort
+    pub local_changes: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// Fetch and work out the steps an update of `project` would run
+pub fn plan_project(
+    project: &LaravelProject,
+    parent: &Path,
+    config: &Config,
+    reference: Option<&str>,
+    force: bool,
+) -> ProjectPlan {
+    let mut plan = ProjectPlan {
+        project: project.name.clone(),
+        target: None,
+        incoming_commits: 0,
+        changed_files: Vec::new(),
+        steps: Vec::new(),
+        skipped: None,
+        local_changes: Vec::new(),
+        error: None,
+    };
+
+    let reference = reference
+        .map(|r| r.to_string())
+        .or_else(|| config.projects.get(&project.name).and_then(|p| p.pinned_ref.clone()));
+
+    let incoming = match GitOperations::incoming(&project.path, reference.as_deref(), config) {
+        Ok(incoming) => incoming,
+        Err(e) => {
+            plan.error = Some(e);
+            return plan;
+        }
+    };
+
+    plan.target = Some(incoming.target.clone());
+
+    // `update_project` stops before any step for these
+    if let SyncStatus::Diverged { .. } = incoming.sync {
+        plan.skipped = Some(format!("{} from {}, not updating", incoming.sync, incoming.target));
+        return plan;
+    }
+    if !incoming.aborting_changes.is_empty() {
+        plan.skipped = Some("local changes found, not updating (policy: abort)".to_string());
+        plan.local_changes = incoming.aborting_changes;
+        return plan;
+    }
+
+    plan.incoming_commits = incoming.commits.len();
+    plan.changed_files = incoming.changed_files.clone();
+
+    match &incoming.sync {
+        SyncStatus::FastForward { .. } | SyncStatus::Switched { .. } => plan.steps.push(PlannedStep {
+            step: "git".to_string(),
+            command: format!("update to {}", incoming.target),
+            details: incoming
+                .commits
+                .iter()
+                .map(|c| format!("{} {}", c.hash, c.subject))
+                .collect(),
+        }),
+        // Nothing to pull, the steps that don't depend on changes still run
+        SyncStatus::Ahead { .. } => plan.steps.push(PlannedStep {
+            step: "git".to_string(),
+            command: format!("nothing to pull, local branch is {}", incoming.sync),
+            details: Vec::new(),
+        }),
+        _ => {}
+    }
+
+    let path = Path::new(&project.path);
+    let pipeline = match Pipeline::for_project_at(path, &incoming.target) {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            plan.error = Some(e);
+            return plan;
+        }
+    };
+
+    let changes = ChangeSet::classify(&incoming.changed_files, &pipeline, &build_inputs(path, config));
+    for step in pipeline.triggered(&incoming.changed_files, force) {
+        let matched = changes.for_step(&step.name);
+
+        let (command, details) = match step.builtin {
+            Some(Builtin::JsBuild) => match build_key(path, &incoming.target, config) {
+                Ok(build) => {
+                    let site = BuildSite::for_project(path, parent, config);
+                    let build_dir = site.builds_dir.join(&build.key);
+                    let command = if is_complete_build(&build_dir) {
+                        format!("link existing {}", build_dir.display())
+                    } else {
+                        format!("build {} in {}", build_dir.display(), site.repo.display())
+                    };
+                    (command, matched)
+                }
+                Err(e) => ("unknown".to_string(), vec![e]),
+            },
+            _ => (step.command(), matched),
+        };
+
+        plan.steps.push(PlannedStep {
+            step: step.name.clone(),
+            command,
+            details,
+        });
+    }
+
+    plan
+}
+
+/// Print plans as a table: one row per step, trigger files below it
+pub fn print_plan_table(plans: &[ProjectPlan]) {
+    let rows = plans
+        .iter()
+        .flat_map(|plan| {
+            if let Some(error) = &plan.error {
+                return vec![(plan.project.clone(), "error".to_string(), error.clone(), Vec::new())];
+            }
+            if let Some(reason) = &plan.skipped {
+                return vec![(plan.project.clone(), "skip".to_string(), reason.clone(), plan.local_changes.clone())];
+            }
+            if plan.steps.is_empty() {
+                return vec![(plan.project.clone(), "-".to_string(), "nothing to do".to_string(), Vec::new())];
+            }
+            plan.steps
+                .iter()
+                .map(|s| (plan.project.clone(), s.step.clone(), s.command.clone(), s.details.clone()))
+                .collect()
+        })
+        .collect::<Vec<_>>();
+
+    let project_width = rows.iter().map(|r| r.0.chars().count()).max().unwrap_or(0).max(7);
+    let step_width = rows.iter().map(|r| r.1.chars().count()).max().unwrap_or(0).max(4);
+
+    println!("{:<pw$}  {:<sw$}  {}", "Project".bold(), "Step".bold(), "Action".bold(), pw = project_width, sw = step_width);
+    println!("{}", "─".repeat(project_width + step_width + 50));
+
+    let mut last_project = String::new();
+    for (project, step, command, details) in rows {
+        let name = if project == last_project { String::new() } else { project.clone() };
+        let step_colored = match step.as_str() {
+            "error" => step.red(),
+            "skip" => step.yellow(),
+            "-" => step.normal(),
+            _ => step.cyan(),
+        };
+        println!("{:<pw$}  {:<sw$}  {}", name.green(), step_colored, command, pw = project_width, sw = step_width);
+        for detail in details {
+            println!("{:<pw$}  {:<sw$}    {}", "", "", detail.dimmed(), pw = project_width, sw = step_width);
+        }
+        last_project = project;
+    }
+}
@@ -0,0 +1,268 @@
+use std::fs;
+use std::path::Path;
+
+use crate::git::short_hash;
+use crate::logger::{LogEntry, LogLevel};
+
+/// File formats of `logs --export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Html,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            "html" | "htm" => Ok(ExportFormat::Html),
+            _ => Err(format!("unknown format {}, expected json, csv or html", value)),
+        }
+    }
+}
+
+impl std::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Html => "html",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ExportFormat {
+    /// Guess the format from the file extension, JSON if it doesn't tell
+    pub fn from_path(path: &Path) -> Self {
+        path.extension()
+            .and_then(|ext| ext.to_str()?.parse().ok())
+            .unwrap_or(ExportFormat::Json)
+    }
+}
+
+/// Write `entries` to `path`
+pub fn export_logs(entries: &[LogEntry], path: &Path, format: ExportFormat) -> Result<(), String> {
+    let content = match format {
+        ExportFormat::Json => serde_json::to_string_pretty(entries).map_err(|e| e.to_string())?,
+        ExportFormat::Csv => to_csv(entries),
+        ExportFormat::Html => to_html(entries),
+    };
+
+    fs::write(path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+fn to_csv(entries: &[LogEntry]) -> String {
+    let mut csv = String::from(
+        "timestamp,level,run_id,user,project,step,command,exit_code,duration_ms,git_before,git_after,message,details\n",
+    );
+
+    for entry in entries {
+        let f = &entry.fields;
+        let row = [
+            entry.timestamp.clone(),
+            level_name(entry.level).to_string(),
+            entry.run_id.clone().unwrap_or_default(),
+            entry.user.clone().unwrap_or_default(),
+            f.project.clone().unwrap_or_default(),
+            f.step.clone().unwrap_or_default(),
+            f.command.clone().unwrap_or_default(),
+            f.exit_code.map(|c| c.to_string()).unwrap_or_default(),
+            f.duration_ms.map(|ms| ms.to_string()).unwrap_or_default(),
+            f.git_before.clone().unwrap_or_default(),
+            f.git_after.clone().unwrap_or_default(),
+            entry.message.clone(),
+            entry.details.clone().unwrap_or_default(),
+        ];
+        csv.push_str(&row.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// Quote a field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn level_name(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Info => "info",
+        LogLevel::Warning => "warning",
+        LogLevel::Error => "error",
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Entries grouped by key, groups in order of their first entry
+fn group_by<'a>(entries: &[&'a LogEntry], key: impl Fn(&LogEntry) -> Option<String>) -> Vec<(Option<String>, Vec<&'a LogEntry>)> {
+    let mut groups: Vec<(Option<String>, Vec<&LogEntry>)> = Vec::new();
+    for &entry in entries {
+        let k = key(entry);
+        match groups.iter_mut().find(|(g, _)| *g == k) {
+            Some((_, group)) => group.push(entry),
+            None => groups.push((k, vec![entry])),
+        }
+    }
+    groups
+}
+
+const STYLE: &str = "
+body { font: 14px/1.4 -apple-system, 'Segoe UI', Roboto, sans-serif; margin: 2em auto; max-width: 1100px; color: #222; }
+h1 { margin-bottom: 0; }
+.meta { color: #666; margin-top: .3em; }
+section.run { border: 1px solid #ddd; border-radius: 6px; margin: 1.5em 0; padding: 0 1em 1em; }
+section.run.failed { border-color: #d33; }
+h2 { font-size: 1.1em; margin: .8em 0 .2em; }
+h3 { font-size: 1em; margin: 1em 0 .3em; color: #444; }
+.badge { font-size: .8em; padding: .1em .5em; border-radius: 3px; margin-left: .5em; color: #fff; background: #2a2; }
+.failed > h2 .badge, .badge.failed { background: #d33; }
+.badge.warned { background: #d90; }
+table { border-collapse: collapse; width: 100%; }
+td { border-top: 1px solid #eee; padding: .3em .5em; vertical-align: top; }
+td.time, td.level, td.took { white-space: nowrap; color: #666; }
+tr.error { background: #fdecec; }
+tr.error td.level { color: #c00; font-weight: bold; }
+tr.warning { background: #fff7e0; }
+tr.warning td.level { color: #a60; font-weight: bold; }
+code { background: #f4f4f4; padding: 0 .3em; border-radius: 3px; }
+details summary { cursor: pointer; color: #555; }
+pre { background: #f7f7f7; padding: .6em; overflow-x: auto; white-space: pre-wrap; margin: .3em 0; }
+";
+
+/// Self-contained report: one section per run, entries grouped by project,
+/// failures highlighted and command output collapsed
+fn to_html(entries: &[LogEntry]) -> String {
+    let generated = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+    let range = match (entries.first(), entries.last()) {
+        (Some(first), Some(last)) => format!("{} – {}", first.timestamp, last.timestamp),
+        _ => "no entries".to_string(),
+    };
+    let errors = entries.iter().filter(|e| e.level == LogLevel::Error).count();
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>Sahakari deployment log</title>\n<style>{}</style>\n</head>\n<body>\n\
+         <h1>Sahakari deployment log</h1>\n<p class=\"meta\">{} · {} entries, {} error(s) · generated {}</p>\n",
+        STYLE,
+        escape(&range),
+        entries.len(),
+        errors,
+        generated
+    );
+
+    let all = entries.iter().collect::<Vec<_>>();
+    for (run_id, run_entries) in group_by(&all, |e| e.run_id.clone()) {
+        let failed = run_entries.iter().any(|e| e.level == LogLevel::Error);
+        let warned = run_entries.iter().any(|e| e.level == LogLevel::Warning);
+        let badge = if failed {
+            "<span class=\"badge failed\">failed</span>"
+        } else if warned {
+            "<span class=\"badge warned\">warnings</span>"
+        } else {
+            "<span class=\"badge\">ok</span>"
+        };
+
+        let title = match &run_id {
+            Some(id) => format!("Run <code>{}</code>", escape(id)),
+            None => "Outside of update runs".to_string(),
+        };
+        let user = run_entries.iter().find_map(|e| e.user.clone());
+        let first = run_entries.first().map(|e| e.timestamp.as_str()).unwrap_or("");
+        let last = run_entries.last().map(|e| e.timestamp.as_str()).unwrap_or("");
+
+        html.push_str(&format!(
+            "<section class=\"run{}\">\n<h2>{}{}</h2>\n<p class=\"meta\">{} – {}{}</p>\n",
+            if failed { " failed" } else { "" },
+            title,
+            badge,
+            escape(first),
+            escape(last),
+            user.map(|u| format!(" · by {}", escape(&u))).unwrap_or_default()
+        ));
+
+        for (project, project_entries) in group_by(&run_entries, |e| e.fields.project.clone()) {
+            let heading = project.map(|p| escape(&p)).unwrap_or_else(|| "General".to_string());
+            let project_failed = project_entries.iter().any(|e| e.level == LogLevel::Error);
+            html.push_str(&format!(
+                "<h3>{}{}</h3>\n<table>\n",
+                heading,
+                if project_failed { "<span class=\"badge failed\">failed</span>" } else { "" }
+            ));
+            for entry in project_entries {
+                html.push_str(&html_row(entry));
+            }
+            html.push_str("</table>\n");
+        }
+
+        html.push_str("</section>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn html_row(entry: &LogEntry) -> String {
+    let f = &entry.fields;
+    let (summary, rest) = match entry.message.split_once('\n') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (entry.message.as_str(), None),
+    };
+
+    let mut cell = escape(summary);
+    if let Some(step) = &f.step {
+        cell = format!("<b>{}</b> {}", escape(step), cell);
+    }
+    if let Some(command) = &f.command {
+        cell.push_str(&format!("<br><code>{}</code>", escape(command)));
+    }
+    if let Some(code) = f.exit_code {
+        cell.push_str(&format!(" exit {}", code));
+    }
+    if let (Some(before), Some(after)) = (&f.git_before, &f.git_after)
+        && before != after
+    {
+        cell.push_str(&format!("<br>git <code>{}</code> → <code>{}</code>", escape(short_hash(before)), escape(short_hash(after))));
+    }
+
+    let output = [rest.map(str::trim), entry.details.as_deref()]
+        .into_iter()
+        .flatten()
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if !output.is_empty() {
+        // Failures show their output right away, everything else on click
+        let open = if entry.level == LogLevel::Error { " open" } else { "" };
+        cell.push_str(&format!("<details{}><summary>Output</summary><pre>{}</pre></details>", open, escape(&output)));
+    }
+
+    let took = f
+        .duration_ms
+        .map(|ms| format!("{:.1}s", ms as f64 / 1000.0))
+        .unwrap_or_default();
+
+    format!(
+        "<tr class=\"{}\"><td class=\"time\">{}</td><td class=\"level\">{}</td><td>{}</td><td class=\"took\">{}</td></tr>\n",
+        level_name(entry.level),
+        escape(&entry.timestamp),
+        entry.level.to_string().trim(),
+        cell,
+        took
+    )
+}
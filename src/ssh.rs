@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+/// How ssh treats hosts that are not in the known_hosts file yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyChecking {
+    /// Remember unknown hosts on first connect, refuse changed keys
+    #[default]
+    AcceptNew,
+    /// Only connect to hosts already in known_hosts (see `git trust`)
+    Yes,
+    /// Never check host keys
+    No,
+}
+
+impl std::fmt::Display for HostKeyChecking {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let value = match self {
+            HostKeyChecking::AcceptNew => "accept-new",
+            HostKeyChecking::Yes => "yes",
+            HostKeyChecking::No => "no",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SshConfig {
+    /// Deploy key per remote host, used when the project has no key of its own
+    #[serde(default)]
+    pub keys: HashMap<String, String>,
+    /// Key used for hosts without an entry in `keys`
+    #[serde(default)]
+    pub default_key: Option<String>,
+    /// known_hosts file used for every git ssh connection
+    #[serde(default = "default_known_hosts")]
+    pub known_hosts: String,
+    #[serde(default)]
+    pub host_key_checking: HostKeyChecking,
+}
+
+fn default_known_hosts() -> String {
+    let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
+    home.join(".sahakari/known_hosts").to_string_lossy().to_string()
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        SshConfig {
+            keys: HashMap::new(),
+            default_key: None,
+            known_hosts: default_known_hosts(),
+            host_key_checking: HostKeyChecking::default(),
+        }
+    }
+}
+
+/// Whether a remote URL is reached over ssh
+pub fn is_ssh_url(url: &str) -> bool {
+    if let Some((scheme, _)) = url.split_once("://") {
+        return scheme == "ssh" || scheme == "git+ssh";
+    }
+    // scp-like `user@host:path`
+    url.contains(':')
+}
+
+/// Quote `value` for `sh`, single quotes included
+pub(crate) fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Pick the deploy key for `project` on `host`: the project's own key first,
+/// then the host key, then the default key.
+pub fn deploy_key(config: &Config, host: &str, project: &str) -> Option<String> {
+    config
+        .projects
+        .get(project)
+        .and_then(|p| p.deploy_key.clone())
+        .or_else(|| config.ssh.keys.get(host).cloned())
+        .or_else(|| config.ssh.default_key.clone())
+}
+
+/// Build the `GIT_SSH_COMMAND` for one git invocation
+pub fn git_ssh_command(config: &Config, host: &str, project: &str) -> Result<String, String> {
+    let mut parts = vec!["ssh".to_string()];
+
+    if let Some(key) = deploy_key(config, host, project) {
+        if !Path::new(&key).is_file() {
+            return Err(format!("Deploy key {} for {} does not exist", key, project));
+        }
+        parts.push(format!("-i {}", shell_quote(&key)));
+        parts.push("-o IdentitiesOnly=yes".to_string());
+    }
+
+    if let Some(dir) = Path::new(&config.ssh.known_hosts).parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    parts.push(format!("-o UserKnownHostsFile={}", shell_quote(&config.ssh.known_hosts)));
+    parts.push(format!("-o StrictHostKeyChecking={}", config.ssh.host_key_checking));
+    // Never sit waiting for a passphrase or password
+    parts.push("-o BatchMode=yes".to_string());
+
+    Ok(parts.join(" "))
+}
+
+/// Add the host keys of `host` to the configured known_hosts file.
+/// Returns the number of keys added.
+pub fn trust_host(config: &Config, host: &str) -> Result<usize, String> {
+    let output = Command::new("ssh-keyscan")
+        .args(["-H", host])
+        .output()
+        .map_err(|e| format!("Failed to run ssh-keyscan: {}", e))?;
+
+    let keys = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !l.starts_with('#'))
+        .map(|l| format!("{}\n", l))
+        .collect::<Vec<_>>();
+
+    if keys.is_empty() {
+        return Err(format!(
+            "No host keys received from {}: {}",
+            host,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    if let Some(dir) = Path::new(&config.ssh.known_hosts).parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.ssh.known_hosts)
+        .map_err(|e| e.to_string())?;
+    file.write_all(keys.concat().as_bytes()).map_err(|e| e.to_string())?;
+
+    Ok(keys.len())
+}
@@ -2,13 +2,58 @@ use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
 
+/// Commits that only exist on one side of a diverged branch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Divergence {
+    pub local_commits: Vec<String>,
+    pub remote_commits: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProjectState {
     pub project_name: String,
     pub failed_step: String,
     pub timestamp: String,
+    #[serde(default)]
+    pub divergence: Option<Divergence>,
+}
+
+/// What a project looked like before an update, used by `rollback`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeployRecord {
+    pub project_name: String,
+    /// When the update started, also identifies the record
+    pub timestamp: String,
+    pub previous_head: String,
+    /// Branch that was checked out, `None` for a detached HEAD
+    #[serde(default)]
+    pub previous_branch: Option<String>,
+    #[serde(default)]
+    pub new_head: Option<String>,
+    /// Target of `public/build` before the update
+    #[serde(default)]
+    pub previous_build: Option<String>,
+    /// Last migration batch before and after `artisan migrate` ran
+    #[serde(default)]
+    pub migration_batch_before: Option<u32>,
+    #[serde(default)]
+    pub migration_batch_after: Option<u32>,
+}
+
+impl DeployRecord {
+    /// Whether the update moved HEAD or applied migrations, i.e. there is something to roll back
+    pub fn changed_anything(&self) -> bool {
+        self.new_head.is_some()
+            || matches!(
+                (self.migration_batch_before, self.migration_batch_after),
+                (Some(before), Some(after)) if after > before
+            )
+    }
 }
 
+/// Deploy records kept per project
+const DEPLOY_HISTORY: usize = 10;
+
 pub struct StateManager {
     state_path: String,
 }
@@ -26,12 +71,22 @@ impl StateManager {
     }
     
     pub fn save_state(&self, project_name: &str, failed_step: &str) {
+        self.write_state(project_name, failed_step, None);
+    }
+
+    /// Flag a project whose branch has diverged from origin
+    pub fn save_divergence(&self, project_name: &str, divergence: Divergence) {
+        self.write_state(project_name, "diverged", Some(divergence));
+    }
+
+    fn write_state(&self, project_name: &str, failed_step: &str, divergence: Option<Divergence>) {
         let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
         
         let state = ProjectState {
             project_name: project_name.to_string(),
             failed_step: failed_step.to_string(),
             timestamp: now,
+            divergence,
         };
         
         let state_file_path = Path::new(&self.state_path).join(format!("{}.json", project_name));
@@ -51,10 +106,7 @@ impl StateManager {
         
         match fs::read_to_string(&state_file_path) {
             Ok(content) => {
-                match serde_json::from_str::<ProjectState>(&content) {
-                    Ok(state) => Some(state),
-                    Err(_) => None,
-                }
+                serde_json::from_str::<ProjectState>(&content).ok()
             },
             Err(_) => None,
         }
@@ -67,4 +119,45 @@ impl StateManager {
             let _ = fs::remove_file(&state_file_path);
         }
     }
-}
\ No newline at end of file
+
+    fn deploys_file(&self, project_name: &str) -> std::path::PathBuf {
+        Path::new(&self.state_path).join("deploys").join(format!("{}.json", project_name))
+    }
+
+    /// Recorded deploys of a project, oldest first
+    pub fn deploy_history(&self, project_name: &str) -> Vec<DeployRecord> {
+        fs::read_to_string(self.deploys_file(project_name))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn write_deploys(&self, project_name: &str, records: &[DeployRecord]) {
+        let path = self.deploys_file(project_name);
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Ok(json) = serde_json::to_string_pretty(records) {
+            fs::write(&path, json).expect("Failed to write deploy history");
+        }
+    }
+
+    /// Add a deploy record, or replace the one with the same timestamp
+    pub fn save_deploy(&self, record: &DeployRecord) {
+        let mut records = self.deploy_history(&record.project_name);
+        match records.iter_mut().find(|r| r.timestamp == record.timestamp) {
+            Some(existing) => *existing = record.clone(),
+            None => records.push(record.clone()),
+        }
+        let skip = records.len().saturating_sub(DEPLOY_HISTORY);
+        self.write_deploys(&record.project_name, &records[skip..]);
+    }
+
+    /// Remove and return the latest deploy record
+    pub fn pop_deploy(&self, project_name: &str) -> Option<DeployRecord> {
+        let mut records = self.deploy_history(project_name);
+        let last = records.pop();
+        self.write_deploys(project_name, &records);
+        last
+    }
+}
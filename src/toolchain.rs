@@ -0,0 +1,193 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::ssh::shell_quote;
+
+/// Heap limit for node when nothing else is configured, in MB
+const DEFAULT_MEMORY_LIMIT: u32 = 1024;
+
+/// Untracked files of the build repo linked into every build worktree by default
+const DEFAULT_LINK_PATHS: [&str; 1] = [".env*"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageManager {
+    Pnpm,
+    Npm,
+    Yarn,
+}
+
+impl std::fmt::Display for PackageManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let value = match self {
+            PackageManager::Pnpm => "pnpm",
+            PackageManager::Npm => "npm",
+            PackageManager::Yarn => "yarn",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+impl PackageManager {
+    /// Pick the package manager from the lockfile in the project root, pnpm without one
+    pub fn detect(has_file: impl Fn(&str) -> bool) -> Self {
+        if has_file("pnpm-lock.yaml") {
+            PackageManager::Pnpm
+        } else if has_file("yarn.lock") {
+            PackageManager::Yarn
+        } else if has_file("package-lock.json") {
+            PackageManager::Npm
+        } else {
+            PackageManager::Pnpm
+        }
+    }
+
+    fn install_command(&self) -> &'static str {
+        match self {
+            // The shared store lets every build hard link the same packages
+            PackageManager::Pnpm => "pnpm install --store-dir {store}",
+            PackageManager::Npm => "npm ci",
+            PackageManager::Yarn => "yarn install --frozen-lockfile",
+        }
+    }
+
+    fn build_command(&self) -> &'static str {
+        match self {
+            PackageManager::Pnpm => "pnpm vite build --outDir {out_dir} --emptyOutDir",
+            PackageManager::Npm => "npx vite build --outDir {out_dir} --emptyOutDir",
+            PackageManager::Yarn => "yarn vite build --outDir {out_dir} --emptyOutDir",
+        }
+    }
+}
+
+/// How JS assets are installed and built. Every field is optional: project
+/// settings override the global ones, anything left unset uses the defaults
+/// of the detected package manager.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ToolchainConfig {
+    /// pnpm, npm or yarn, detected from the lockfile when unset
+    #[serde(default)]
+    pub package_manager: Option<PackageManager>,
+    /// Shell command installing dependencies, `{store}` is the shared pnpm store
+    #[serde(default)]
+    pub install: Option<String>,
+    /// Shell command building the assets, `{out_dir}` is where they must end up
+    #[serde(default)]
+    pub build: Option<String>,
+    /// Directory, relative to the project, the build command writes to.
+    /// Only needed when the build command can't use `{out_dir}`, e.g. Laravel Mix.
+    #[serde(default)]
+    pub output_dir: Option<String>,
+    /// Extra environment variables for install and build
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// `--max-old-space-size` for node in MB, 0 for no limit
+    #[serde(default)]
+    pub memory_limit: Option<u32>,
+    /// Globs, relative to the build repo, of untracked files or directories to symlink
+    /// into the build worktree, `.env*` when unset. Add `vendor` for configs that import from it.
+    #[serde(default)]
+    pub link_paths: Option<Vec<String>>,
+}
+
+/// Toolchain settings resolved for one project
+#[derive(Debug, Clone)]
+pub struct Toolchain {
+    pub package_manager: PackageManager,
+    pub install: String,
+    pub build: String,
+    pub output_dir: Option<String>,
+    pub env: BTreeMap<String, String>,
+    pub memory_limit: u32,
+    pub link_paths: Vec<String>,
+}
+
+impl Toolchain {
+    /// Merge the project's settings over the global ones; `has_file` tells
+    /// whether a file exists in the project root, for lockfile detection.
+    pub fn resolve(config: &Config, project_name: &str, has_file: impl Fn(&str) -> bool) -> Self {
+        let global = &config.toolchain;
+        let project = config
+            .projects
+            .get(project_name)
+            .and_then(|p| p.toolchain.clone())
+            .unwrap_or_default();
+
+        let package_manager = project
+            .package_manager
+            .or(global.package_manager)
+            .unwrap_or_else(|| PackageManager::detect(has_file));
+
+        let mut env = global.env.clone().into_iter().collect::<BTreeMap<_, _>>();
+        env.extend(project.env);
+
+        Toolchain {
+            package_manager,
+            install: project
+                .install
+                .or_else(|| global.install.clone())
+                .unwrap_or_else(|| package_manager.install_command().to_string()),
+            build: project
+                .build
+                .or_else(|| global.build.clone())
+                .unwrap_or_else(|| package_manager.build_command().to_string()),
+            output_dir: project.output_dir.or_else(|| global.output_dir.clone()),
+            env,
+            memory_limit: project
+                .memory_limit
+                .or(global.memory_limit)
+                .unwrap_or(DEFAULT_MEMORY_LIMIT),
+            link_paths: project
+                .link_paths
+                .or_else(|| global.link_paths.clone())
+                .unwrap_or_else(|| DEFAULT_LINK_PATHS.iter().map(|p| p.to_string()).collect()),
+        }
+    }
+
+    /// Everything that changes the build output, hashed into the build key
+    pub fn describe(&self) -> String {
+        let mut lines = vec![
+            format!("install: {}", self.install),
+            format!("build: {}", self.build),
+        ];
+        if let Some(dir) = &self.output_dir {
+            lines.push(format!("output_dir: {}", dir));
+        }
+        for (name, value) in &self.env {
+            lines.push(format!("env: {}={}", name, value));
+        }
+        for path in &self.link_paths {
+            lines.push(format!("link: {}", path));
+        }
+        lines.join("\n")
+    }
+
+    /// Run one of the toolchain's shell commands in `dir`
+    pub fn run(&self, dir: &Path, command: &str, placeholders: &[(&str, &str)]) -> Result<(), String> {
+        let mut command = command.to_string();
+        for (name, value) in placeholders {
+            command = command.replace(&format!("{{{}}}", name), &shell_quote(value));
+        }
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", &command]).current_dir(dir);
+        if self.memory_limit > 0 {
+            cmd.env("NODE_OPTIONS", format!("--max-old-space-size={}", self.memory_limit));
+        }
+        // Configured variables win, including NODE_OPTIONS
+        cmd.envs(&self.env);
+
+        let output = cmd.output().map_err(|e| e.to_string())?;
+        if !output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("Command `{}` failed:\n{}\n{}", command, stdout, stderr));
+        }
+
+        Ok(())
+    }
+}